@@ -1,73 +1,800 @@
-use color_eyre::eyre::{bail, Context as _, OptionExt, Result};
+use color_eyre::eyre::{bail, eyre, Context as _, Report, Result};
 use color_eyre::{Section as _, SectionExt as _};
+use ram_probe_rs::backtrace::DebugInfo;
+#[cfg(feature = "defmt")]
 use ram_probe_rs::defmt::DefmtInfo;
-use ram_probe_rs::elf::Parser;
+use ram_probe_rs::elf::{
+    Footprint, Parser, Segments, VectorTable, DEFAULT_RTT_SYMBOL, DEFAULT_VECTOR_TABLE_SECTION,
+};
+use ram_probe_rs::image::{self, ImageFormat};
+use ram_probe_rs::probe_rs::architecture::arm::ArmError;
 use ram_probe_rs::probe_rs::config::get_target_by_name;
-use ram_probe_rs::run::{DefmtOpts, DefmtRunner};
+use ram_probe_rs::probe_rs::probe::DebugProbeError;
+use ram_probe_rs::probe_rs::{rtt, Target};
+use ram_probe_rs::run::{
+    ram_image, ChannelSelector, CoreImage, Decoding, Exit, HardFault, Input, InputMode,
+    ResetStrategy, Route, RouteSink, Routing, RunOpts, Runner, Source, StopReason, Stopped,
+    VectorCatch,
+};
 use ram_probe_rs::session::{connect, ProbeArgs};
+use std::fs::File;
+use std::io::BufWriter;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The exit code if the program hit the hard fault handler.
+const EXIT_HARD_FAULT: u8 = 134;
+/// The exit code if the program didn't exit before `--timeout`.
+const EXIT_TIMEOUT: u8 = 124;
+/// The exit code for debug probe errors.
+const EXIT_PROBE_ERROR: u8 = 125;
+/// The exit code if the run was interrupted by SIGINT or SIGTERM.
+const EXIT_INTERRUPTED: u8 = 130;
 
 #[derive(Debug, Clone, clap::Parser)]
-#[command(version = "1.0", about = "Flash and run an ELF program from RAM")]
+#[command(version = "1.0", about = "Flash and run programs from RAM")]
 struct Args {
-    /// The path to the ELF file to flash and run from RAM
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+enum Command {
+    /// Flash and run a program from RAM, the default without a subcommand
+    Run(Box<RunArgs>),
+    /// Export the RAM image that would be written to the target
+    Export(ExportArgs),
+    /// Print section sizes and RAM usage of an ELF program
+    Size(SizeArgs),
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct ImageArgs {
+    /// The path to the ELF (or HEX, SREC, BIN) file to flash and run from RAM
     path: String,
 
+    /// The image format, guessed from the file extension by default
+    #[clap(long, value_enum)]
+    format: Option<ImageFormat>,
+
+    /// The load address of a raw binary image
+    #[clap(long, value_parser = parse_address)]
+    base_address: Option<u32>,
+
+    /// Relocate the ELF program to the first RAM region large enough
+    #[clap(long)]
+    relocate: bool,
+
+    /// Relocate the ELF program to this address
+    #[clap(long, value_parser = parse_address)]
+    load_address: Option<u32>,
+
+    /// The stack size used to check the ELF program's RAM footprint
+    #[clap(long)]
+    stack_size: Option<u32>,
+
+    /// The heap size used to check the ELF program's RAM footprint
+    #[clap(long)]
+    heap_size: Option<u32>,
+
+    /// The ELF section containing the vector table
+    #[clap(long, default_value = DEFAULT_VECTOR_TABLE_SECTION)]
+    vector_table_section: String,
+}
+
+/// How RTT output is decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Decoder {
+    /// `defmt` on channel 0 if the ELF file contains a `defmt` table, otherwise text
+    Auto,
+    /// `defmt` frames from up channel 0
+    #[cfg(feature = "defmt")]
+    Defmt,
+    /// UTF-8 text from all up channels
+    Text,
+    /// Raw bytes from all up channels
+    Raw,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct RunArgs {
+    #[clap(flatten)]
+    image: ImageArgs,
+
+    /// The RTT control block address, required for images without symbols
+    #[clap(long, value_parser = parse_address)]
+    rtt_address: Option<u32>,
+
+    /// The RTT control block symbol
+    #[clap(long, default_value = DEFAULT_RTT_SYMBOL)]
+    rtt_symbol: String,
+
+    /// How RTT output is decoded
+    #[clap(long, value_enum, default_value_t = Decoder::Auto)]
+    decoder: Decoder,
+
+    /// Decode an up channel (by number or name) as `defmt`, `text` or `raw`, and write it to stdout
+    /// or a file. Can be given several times, e.g. `--channel 1=raw:samples.bin`
+    #[clap(long = "channel", value_name = "CHANNEL=DECODING[:PATH]")]
+    channels: Vec<Route>,
+
+    /// Forward a file or named pipe, or stdin (`-`), to an RTT down channel
+    #[clap(long, value_name = "PATH")]
+    input: Option<Source>,
+
+    /// The down channel (by number or name) that input is forwarded to
+    #[clap(long, default_value = "0", requires = "input")]
+    down_channel: ChannelSelector,
+
+    /// Forward input a line at a time, or as soon as it's read
+    #[clap(long, value_enum, default_value_t = InputMode::Line, requires = "input")]
+    input_mode: InputMode,
+
+    /// A symbol containing the exit status, which is read when the program hits a breakpoint
+    #[clap(long, value_name = "SYMBOL")]
+    exit_code_symbol: Option<String>,
+
+    /// Allow semihosting file I/O in this directory. Without it, only the console can be used
+    #[clap(long, value_name = "DIR")]
+    semihosting_root: Option<PathBuf>,
+
+    /// Catch these faults with the debug vector catch, instead of patching the HardFault handler
+    /// with a breakpoint, e.g. `--vector-catch hard-fault,bus-fault`
+    #[clap(long, value_enum, value_delimiter = ',', value_name = "CATCH")]
+    vector_catch: Vec<VectorCatch>,
+
+    /// Stop the program if it hasn't exited after this many seconds
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+
+    /// Report the throughput of each RTT up channel every second, and how often its buffer was
+    /// full
+    #[clap(long)]
+    stats: bool,
+
+    /// Attach to the program already running on the target, without resetting it or downloading
+    /// the image, e.g. after the probe was disconnected
+    #[clap(long, conflicts_with_all = ["no_verify", "skip_unchanged", "connect_under_reset"])]
+    attach: bool,
+
+    /// How the core is stopped before the image is written. `hardware` connects under reset
+    #[clap(long, value_enum, default_value_t = ResetStrategy::Sequence, conflicts_with = "attach")]
+    reset: ResetStrategy,
+
+    /// Leave the other cores running, instead of resetting them
+    #[clap(long, conflicts_with = "attach")]
+    leave_other_cores: bool,
+
+    /// Run an ELF file on another core, by index in the target description, e.g.
+    /// `--core-elf 1=core1.elf`. Can be given once per core. Its stack and heap sizes are taken
+    /// from its linker symbols, not `--stack-size` and `--heap-size`
+    #[clap(long = "core-elf", value_name = "CORE=PATH")]
+    core_elfs: Vec<CoreElf>,
+
+    /// Don't read back the RAM contents to check the image was written correctly
+    #[clap(long)]
+    no_verify: bool,
+
+    /// Only write the parts of the image that differ from the target's RAM contents
+    #[clap(long)]
+    skip_unchanged: bool,
+
     #[clap(flatten)]
     probe: ProbeArgs,
 }
 
-fn main() -> Result<()> {
+/// An ELF file run on another core than the main one.
+#[derive(Debug, Clone)]
+struct CoreElf {
+    core: usize,
+    path: String,
+}
+
+/// Parses `CORE=PATH`, e.g. `1=core1.elf`.
+impl FromStr for CoreElf {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((core, path)) = s.split_once('=') else {
+            return Err(format!("expected `CORE=PATH`, got `{}`", s));
+        };
+        let core = core
+            .parse()
+            .map_err(|_| format!("invalid core index `{}`", core))?;
+        if core == 0 {
+            return Err("core 0 runs the main image".to_owned());
+        }
+        Ok(Self {
+            core,
+            path: path.to_owned(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct ExportArgs {
+    #[clap(flatten)]
+    image: ImageArgs,
+
+    /// Chip name
+    #[clap(long, env = "PROBE_RS_CHIP")]
+    chip: String,
+
+    /// The path to write the RAM image to
+    #[clap(long, short)]
+    output: PathBuf,
+
+    /// The output format, guessed from the output file extension by default
+    #[clap(long, value_enum)]
+    output_format: Option<ImageFormat>,
+
+    /// The byte used to pad gaps between segments in raw binary output
+    #[clap(long, default_value_t = 0)]
+    fill: u8,
+
+    /// Export the image that `run --vector-catch` downloads, with the HardFault handler unpatched
+    #[clap(long, value_enum, value_delimiter = ',', value_name = "CATCH")]
+    vector_catch: Vec<VectorCatch>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct SizeArgs {
+    /// The path to the ELF file
+    path: String,
+
+    /// Chip name
+    #[clap(long, env = "PROBE_RS_CHIP")]
+    chip: String,
+
+    /// The stack size used to calculate RAM usage
+    #[clap(long)]
+    stack_size: Option<u32>,
+
+    /// The heap size used to calculate RAM usage
+    #[clap(long)]
+    heap_size: Option<u32>,
+
+    /// The ELF section containing the vector table
+    #[clap(long, default_value = DEFAULT_VECTOR_TABLE_SECTION)]
+    vector_table_section: String,
+
+    /// Print the report as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+/// A RAM-only program parsed from an image file.
+struct Program<'data> {
+    elf: Option<Parser<'data>>,
+    segments: Segments<'data>,
+    vector_table: VectorTable,
+    /// The offset added to linked addresses when relocating.
+    offset: u32,
+    /// The RAM used at runtime, for ELF programs.
+    footprint: Option<Footprint>,
+}
+
+fn main() -> Result<ExitCode> {
     color_eyre::install()?;
     try_init_logging()?;
 
-    use clap::Parser as _;
-    let args = Args::parse();
+    let args = parse_args();
+
+    match args.command {
+        Command::Run(args) => Ok(run(*args).unwrap_or_else(|e| {
+            eprintln!("Error: {:?}", e);
+            error_code(&e)
+        })),
+        Command::Export(args) => export(args).map(|()| ExitCode::SUCCESS),
+        Command::Size(args) => size(args).map(|()| ExitCode::SUCCESS),
+    }
+}
+
+/// Parses the command line, running `run` if no subcommand is given, as in
+/// `ram-probe --chip X prog.elf`.
+fn parse_args() -> Args {
+    use clap::{CommandFactory as _, Parser as _};
 
+    let mut args: Vec<_> = std::env::args_os().collect();
+    let command = Args::command();
+    let is_subcommand = |arg: &str| {
+        command.get_subcommands().any(|c| c.get_name() == arg)
+            || ["help", "-h", "--help", "-V", "--version"].contains(&arg)
+    };
+    if args
+        .get(1)
+        .is_some_and(|arg| !arg.to_str().is_some_and(is_subcommand))
+    {
+        args.insert(1, "run".into());
+    }
+    Args::parse_from(args)
+}
+
+/// The exit code of the program's exit status.
+///
+/// Statuses that don't fit in an exit code, or that are one of the reserved exit codes, are
+/// reported as 1.
+fn exit_code(exit: Exit) -> ExitCode {
+    if exit.is_success() {
+        return ExitCode::SUCCESS;
+    }
+    log::error!("program exited with status {}", exit.status);
+    let reserved = [
+        EXIT_HARD_FAULT,
+        EXIT_TIMEOUT,
+        EXIT_PROBE_ERROR,
+        EXIT_INTERRUPTED,
+    ];
+    match u8::try_from(exit.status) {
+        Ok(code) if !reserved.contains(&code) => ExitCode::from(code),
+        _ => ExitCode::from(1),
+    }
+}
+
+/// The exit code of an error, which is [`EXIT_HARD_FAULT`] if the program faulted,
+/// [`EXIT_TIMEOUT`] or [`EXIT_INTERRUPTED`] if it was stopped, or [`EXIT_PROBE_ERROR`] if it was
+/// caused by the debug probe.
+fn error_code(error: &Report) -> ExitCode {
+    if error.chain().any(|e| e.is::<HardFault>()) {
+        return ExitCode::from(EXIT_HARD_FAULT);
+    }
+    if let Some(stopped) = error.downcast_ref::<Stopped>() {
+        return match stopped.reason {
+            StopReason::Timeout(_) => ExitCode::from(EXIT_TIMEOUT),
+            StopReason::Interrupted => ExitCode::from(EXIT_INTERRUPTED),
+        };
+    }
+    let probe = error.chain().any(|e| {
+        e.is::<ram_probe_rs::probe_rs::Error>()
+            || e.is::<ArmError>()
+            || e.is::<DebugProbeError>()
+            || e.is::<rtt::Error>()
+    });
+    if probe {
+        ExitCode::from(EXIT_PROBE_ERROR)
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn run(mut args: RunArgs) -> Result<ExitCode> {
     log::debug!("target `{}`", args.probe.chip);
+    if args.reset == ResetStrategy::Hardware {
+        args.probe.connect_under_reset = true;
+    }
     let target = get_target_by_name(&args.probe.chip)?;
 
+    let data = read_image(&args.image)?;
+    let mut program = load_program(&args.image, &data, &target)?;
+    program.elf = program.elf.map(|elf| elf.with_rtt_symbol(&args.rtt_symbol));
+
+    let rtt_addr = match (args.rtt_address, &program.elf) {
+        (Some(rtt_addr), _) => rtt_addr,
+        (None, Some(elf)) => elf
+            .rtt_address()
+            .ok_or_else(|| eyre!("RTT symbol `{}` not found", args.rtt_symbol))?
+            .wrapping_add(program.offset),
+        (None, None) => bail!("`--rtt-address` is required for images without symbols"),
+    };
+    log::debug!("RTT address 0x{:08x}", rtt_addr);
+    let exit_code_addr = match (&args.exit_code_symbol, &program.elf) {
+        (Some(symbol), Some(elf)) => Some(
+            elf.symbols()
+                .get(symbol)
+                .ok_or_else(|| eyre!("exit code symbol `{}` not found", symbol))?
+                .address
+                .wrapping_add(program.offset),
+        ),
+        (Some(_), None) => bail!("`--exit-code-symbol` requires an ELF file"),
+        (None, _) => None,
+    };
+    let debug_info = program
+        .elf
+        .as_ref()
+        .map(|elf| DebugInfo::new(elf, program.offset))
+        .transpose()?;
+
+    let mut routing = Routing::new(match args.decoder {
+        Decoder::Raw => Decoding::Raw,
+        _ => Decoding::Text,
+    });
+    routing.routes = args.channels;
+    // Channel 0 of each program is decoded as `defmt` if that program has a `defmt` table.
+    #[cfg(feature = "defmt")]
+    let auto = matches!(args.decoder, Decoder::Auto | Decoder::Defmt);
+
+    #[cfg(feature = "defmt")]
+    let defmt = {
+        let routed = routing.routes.iter().any(|r| r.decoding == Decoding::Defmt);
+        let defmt = match &program.elf {
+            Some(_) if auto || routed => DefmtInfo::new(&data)?,
+            _ => None,
+        };
+        match &defmt {
+            Some(defmt) => {
+                if defmt.is_missing_debug() {
+                    log::warn!("defmt locations empty, is the ELF compiled with `debug = 2`?");
+                }
+            }
+            None if (args.decoder == Decoder::Defmt || routed) && program.elf.is_none() => bail!(
+                "`defmt` decoding requires an ELF file, HEX, SREC and BIN images are decoded as text"
+            ),
+            None if args.decoder == Decoder::Defmt || routed => bail!("defmt info not found"),
+            None => log::debug!("defmt info not found, decoding text"),
+        }
+        defmt
+    };
+
+    // Programs on the other cores, which are ELF files run at their linked addresses.
+    let core_args: Vec<_> = args
+        .core_elfs
+        .iter()
+        .map(|core_elf| ImageArgs {
+            path: core_elf.path.clone(),
+            format: Some(ImageFormat::Elf),
+            base_address: None,
+            relocate: false,
+            load_address: None,
+            // each program's stack and heap sizes come from its own linker symbols
+            stack_size: None,
+            heap_size: None,
+            vector_table_section: args.image.vector_table_section.clone(),
+        })
+        .collect();
+    let core_data = core_args
+        .iter()
+        .map(read_image)
+        .collect::<Result<Vec<_>>>()?;
+    let mut core_programs = Vec::new();
+    for (image_args, data) in core_args.iter().zip(&core_data) {
+        let mut program = load_program(image_args, data, &target)?;
+        program.elf = program.elf.map(|elf| elf.with_rtt_symbol(&args.rtt_symbol));
+        core_programs.push(program);
+    }
+    let mut programs = vec![(0, &program)];
+    programs.extend(args.core_elfs.iter().map(|c| c.core).zip(&core_programs));
+    check_footprints(&programs)?;
+    let core_debug_info = core_programs
+        .iter()
+        .map(|program| {
+            let elf = program.elf.as_ref().expect("ELF program");
+            DebugInfo::new(elf, 0)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    #[cfg(feature = "defmt")]
+    let core_defmt = {
+        let routed = routing.routes.iter().any(|r| r.decoding == Decoding::Defmt);
+        core_data
+            .iter()
+            .map(|data| {
+                if auto || routed {
+                    DefmtInfo::new(data)
+                } else {
+                    Ok(None)
+                }
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let mut opts = RunOpts::with_defaults(&program.segments, rtt_addr, &program.vector_table);
+    opts.routing = routing.clone();
+    opts.input = args.input.map(|source| Input {
+        channel: args.down_channel,
+        source,
+        mode: args.input_mode,
+    });
+    #[cfg(feature = "defmt")]
+    {
+        opts.defmt = defmt.as_ref();
+        opts.auto_defmt = auto;
+    }
+    opts.debug_info = debug_info.as_ref();
+    opts.exit_code_addr = exit_code_addr;
+    opts.semihosting_root = args.semihosting_root;
+    opts.verify = !args.no_verify;
+    opts.skip_unchanged = args.skip_unchanged;
+    opts.reset = args.reset;
+    opts.reset_other_cores = !args.leave_other_cores;
+    opts.vector_catch = args.vector_catch;
+    opts.run_timeout = args.timeout;
+    opts.stats = args.stats;
+    opts.attach = args.attach;
+    opts.interrupt = Some(interrupt_flag()?);
+    for (i, core_elf) in args.core_elfs.iter().enumerate() {
+        let program = &core_programs[i];
+        let elf = program.elf.as_ref().expect("ELF program");
+        let rtt_addr = elf.rtt_address().ok_or_else(|| {
+            eyre!(
+                "RTT symbol `{}` not found in `{}`",
+                args.rtt_symbol,
+                core_elf.path
+            )
+        })?;
+        let mut image = CoreImage::new(
+            core_elf.core,
+            &program.segments,
+            rtt_addr,
+            &program.vector_table,
+        );
+        image.exit_code_addr = match &args.exit_code_symbol {
+            Some(symbol) => Some(
+                elf.symbols()
+                    .get(symbol)
+                    .ok_or_else(|| {
+                        eyre!(
+                            "exit code symbol `{}` not found in `{}`",
+                            symbol,
+                            core_elf.path
+                        )
+                    })?
+                    .address,
+            ),
+            None => None,
+        };
+        #[cfg(feature = "defmt")]
+        {
+            image.defmt = core_defmt[i].as_ref();
+        }
+        image.debug_info = Some(&core_debug_info[i]);
+        opts.cores.push(image);
+    }
+    let mut sink = RouteSink::new(routing)?;
+    if !opts.cores.is_empty() {
+        sink = sink.with_core_prefix();
+    }
+
+    let mut session = connect(&args.probe, target)?;
+    let mut runner = Runner::new(&mut session, &opts)?;
+    let exit = runner.run(&mut session, &mut sink)?;
+    Ok(exit_code(exit))
+}
+
+fn export(args: ExportArgs) -> Result<()> {
+    log::debug!("target `{}`", args.chip);
+    let target = get_target_by_name(&args.chip)?;
+
+    let data = read_image(&args.image)?;
+    let program = load_program(&args.image, &data, &target)?;
+    let image = ram_image(&program.segments, &program.vector_table, &args.vector_catch)?;
+
+    let format = args
+        .output_format
+        .unwrap_or_else(|| ImageFormat::from_path(&args.output));
+    log::debug!("writing {:?} image to `{}`", format, args.output.display());
+    let file = File::create(&args.output)
+        .wrap_err("failed to create output file")
+        .with_section(|| args.output.display().to_string().header("Path"))?;
+    image
+        .export(
+            format,
+            program.vector_table.reset,
+            args.fill,
+            BufWriter::new(file),
+        )
+        .wrap_err("failed to write output file")
+        .with_section(|| args.output.display().to_string().header("Path"))?;
+    Ok(())
+}
+
+fn size(args: SizeArgs) -> Result<()> {
+    log::debug!("target `{}`", args.chip);
+    let target = get_target_by_name(&args.chip)?;
+
     log::debug!("reading `{}`", args.path);
     let data = std::fs::read(&args.path)
         .wrap_err("failed to read ELF file")
         .with_section(|| args.path.clone().header("Path"))?;
+    let elf = Parser::new(&data)?.with_vector_table_section(args.vector_table_section);
+
+    let report = elf.size_report(&target, args.stack_size, args.heap_size)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report);
+    }
+    Ok(())
+}
+
+fn read_image(args: &ImageArgs) -> Result<Vec<u8>> {
+    log::debug!("reading `{}`", args.path);
+    std::fs::read(&args.path)
+        .wrap_err("failed to read image file")
+        .with_section(|| args.path.clone().header("Path"))
+}
+
+fn load_program<'data>(
+    args: &ImageArgs,
+    data: &'data [u8],
+    target: &Target,
+) -> Result<Program<'data>> {
+    let format = args
+        .format
+        .unwrap_or_else(|| ImageFormat::from_path(&args.path));
+    log::debug!("image format {:?}", format);
+
+    let program = match format {
+        ImageFormat::Elf => {
+            let elf = Parser::new(data)?.with_vector_table_section(&args.vector_table_section);
 
-    let elf = Parser::new(&data)?;
+            if log::log_enabled!(log::Level::Trace) {
+                use ram_probe_rs::elf::object::ObjectSection as _;
 
-    if log::log_enabled!(log::Level::Trace) {
-        use ram_probe_rs::elf::object::ObjectSection as _;
+                for symbol in elf.symbols().iter() {
+                    log::trace!(
+                        "ELF symbol `{}` at 0x{:08x} ({} bytes)",
+                        symbol.demangled,
+                        symbol.address,
+                        symbol.size
+                    );
+                }
+                for (name, section) in elf.named_sections() {
+                    log::trace!(
+                        "ELF section `{}` at 0x{:08x} ({} bytes)",
+                        name,
+                        section.address(),
+                        section.size()
+                    );
+                }
+            }
 
-        for (name, addr) in elf.named_symbols() {
-            log::trace!("ELF symbol `{}` at 0x{:08x}", name, addr);
+            let vector_table = elf
+                .vector_table()?
+                .ok_or_else(|| {
+                    eyre!(
+                        "vector table not found in section `{}`, a vector table symbol or at the entry point",
+                        args.vector_table_section
+                    )
+                })?;
+            let mut program = if args.relocate || args.load_address.is_some() {
+                let relocation = elf.relocate(target, args.load_address)?;
+                let vector_table = relocation.vector_table(&vector_table)?;
+                Program {
+                    elf: Some(elf),
+                    segments: relocation.segments,
+                    vector_table,
+                    offset: relocation.offset,
+                    footprint: None,
+                }
+            } else {
+                let segments = elf.ram_loadable_segments(target)?;
+                Program {
+                    elf: Some(elf),
+                    segments,
+                    vector_table,
+                    offset: 0,
+                    footprint: None,
+                }
+            };
+
+            let footprint = program
+                .elf
+                .as_ref()
+                .expect("ELF program")
+                .footprint(target, args.stack_size, args.heap_size)?
+                .relocated(program.offset);
+            log::debug!("{}", footprint);
+            footprint.check(target)?;
+            program.footprint = Some(footprint);
+            program
+        }
+        _ if args.relocate || args.load_address.is_some() => {
+            bail!("only ELF programs can be relocated")
         }
-        for (name, section) in elf.named_sections() {
-            log::trace!(
-                "ELF section `{}` at 0x{:08x} ({} bytes)",
-                name,
-                section.address(),
-                section.size()
-            );
+        format => {
+            let segments = image::ram_loadable_segments(data, format, args.base_address, target)?;
+            let vector_table = image::vector_table(&segments)?;
+            Program {
+                elf: None,
+                segments,
+                vector_table,
+                offset: 0,
+                footprint: None,
+            }
         }
+    };
+    log::debug!("{:?}", program.vector_table);
+    for (index, entry) in program.vector_table.entries.iter().enumerate() {
+        log::trace!(
+            "vector {:>3} 0x{:08x} {}",
+            index,
+            entry,
+            program.vector_table.entry_name(index)
+        );
     }
+    program.vector_table.check(&program.segments, target)?;
 
-    let segments = elf.ram_loadable_segments(&target)?;
-    let rtt_addr = elf.rtt_address().ok_or_eyre("RTT symbol not found")?;
-    log::debug!("RTT address 0x{:08x}", rtt_addr);
-    let vector_table = elf
-        .vector_table()?
-        .ok_or_eyre("vector table section not found")?;
-    log::debug!("{:?}", vector_table);
-    let defmt = DefmtInfo::new(&data)?.ok_or_eyre("defmt info not found")?;
-    if defmt.is_missing_debug() {
-        log::warn!("defmt locations empty, is the ELF compiled with `debug = 2`?");
-    }
-    let opts = DefmtOpts::with_defaults(&segments, rtt_addr, &vector_table, &defmt);
+    Ok(program)
+}
 
-    let mut session = connect(&args.probe, target)?;
-    let mut runner = DefmtRunner::new(&mut session, &opts)?;
-    runner.run(&mut session)?;
+/// Check that the RAM used by the programs on different cores doesn't overlap.
+///
+/// This is the footprint of ELF programs, including `.bss` and the stack, and the loaded segments
+/// of other images.
+fn check_footprints(programs: &[(usize, &Program)]) -> Result<()> {
+    let used: Vec<_> = programs
+        .iter()
+        .map(|(core, program)| (core, used_ram(program)))
+        .collect();
+    for (i, (core, entries)) in used.iter().enumerate() {
+        for (other_core, other_entries) in used[..i].iter() {
+            for (name, a) in entries.iter() {
+                for (other_name, b) in other_entries.iter() {
+                    if a.start < b.end && b.start < a.end {
+                        bail!(
+                            "`{}` of core {} (0x{:08x}..0x{:08x}) overlaps `{}` of core {} (0x{:08x}..0x{:08x})",
+                            name,
+                            core,
+                            a.start,
+                            a.end,
+                            other_name,
+                            other_core,
+                            b.start,
+                            b.end
+                        );
+                    }
+                }
+            }
+        }
+    }
     Ok(())
 }
 
+/// The named address ranges of RAM used by a program.
+fn used_ram(program: &Program) -> Vec<(String, Range<u64>)> {
+    match &program.footprint {
+        Some(footprint) => footprint
+            .entries
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.range.clone()))
+            .collect(),
+        None => program
+            .segments
+            .iter()
+            .map(|(address, data)| {
+                let range = *address..*address + data.len() as u64;
+                (format!("segment at 0x{:08x}", address), range)
+            })
+            .collect(),
+    }
+}
+
+/// Parse a duration in seconds, e.g. `1.5`.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{}", e))
+}
+
+/// A flag that is set on SIGINT or SIGTERM, so the runner can stop the program cleanly.
+///
+/// A second signal exits immediately.
+fn interrupt_flag() -> Result<Arc<AtomicBool>> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::flag;
+
+    let interrupt = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        flag::register_conditional_shutdown(signal, EXIT_INTERRUPTED.into(), interrupt.clone())?;
+        flag::register(signal, interrupt.clone())?;
+    }
+    Ok(interrupt)
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal address.
+fn parse_address(value: &str) -> Result<u32, std::num::ParseIntError> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+}
+
 fn try_init_logging() -> Result<()> {
     let mut builder = pretty_env_logger::formatted_builder();
     match std::env::var("RUST_LOG") {
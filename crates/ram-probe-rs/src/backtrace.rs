@@ -0,0 +1,320 @@
+use crate::elf::{ObjectSection as _, Parser, SymbolIndex};
+use eyre::{eyre, Result};
+use gimli::{
+    BaseAddresses, CfaRule, DebugFrame, Dwarf, EndianRcSlice, Register, RegisterRule,
+    RunTimeEndian, UnwindContext, UnwindSection as _,
+};
+use probe_rs::{Core, MemoryInterface as _};
+use std::fmt;
+use std::rc::Rc;
+
+type Reader = EndianRcSlice<RunTimeEndian>;
+
+/// The stack pointer register number, in both DWARF and the core.
+const SP: u16 = 13;
+/// The link register number, in both DWARF and the core.
+const LR: u16 = 14;
+/// The program counter register number, in both DWARF and the core.
+const PC: u16 = 15;
+
+/// Stop unwinding after this many frames, e.g. in case of a corrupted stack.
+const MAX_FRAMES: usize = 64;
+
+/// The ELF's DWARF debug info, used to unwind the stack and find source locations.
+pub struct DebugInfo<'a> {
+    debug_frame: Option<DebugFrame<Reader>>,
+    context: Option<addr2line::Context<Reader>>,
+    symbols: &'a SymbolIndex<'a>,
+    /// The offset added to linked addresses when relocating.
+    offset: u32,
+}
+
+impl<'a> DebugInfo<'a> {
+    pub fn new(elf: &'a Parser<'a>, offset: u32) -> Result<Self> {
+        let endian = RunTimeEndian::Little;
+        let load = |name: &str| -> Reader {
+            let data = elf
+                .named_sections()
+                .find(|(section, _)| *section == name)
+                .and_then(|(_, section)| section.data().ok())
+                .unwrap_or_default();
+            EndianRcSlice::new(Rc::from(data), endian)
+        };
+
+        let debug_frame = elf
+            .named_sections()
+            .any(|(name, _)| name == ".debug_frame")
+            .then(|| {
+                let mut debug_frame = DebugFrame::from(load(".debug_frame"));
+                debug_frame.set_address_size(4);
+                debug_frame
+            });
+        if debug_frame.is_none() {
+            log::warn!("`.debug_frame` section not found, backtraces will be incomplete");
+        }
+
+        let context = if elf.named_sections().any(|(name, _)| name == ".debug_info") {
+            let dwarf = Dwarf::load(|id| Ok::<_, gimli::Error>(load(id.name())))?;
+            Some(addr2line::Context::from_dwarf(dwarf)?)
+        } else {
+            log::warn!("`.debug_info` section not found, backtraces won't have source locations");
+            None
+        };
+
+        Ok(Self {
+            debug_frame,
+            context,
+            symbols: elf.symbols(),
+            offset,
+        })
+    }
+
+    /// The linked address of a runtime `address`.
+    fn linked(&self, address: u32) -> u32 {
+        address.wrapping_sub(self.offset)
+    }
+
+    /// The functions and source locations of the code at runtime address `pc`.
+    ///
+    /// Inlined functions are returned first, followed by the function they were inlined into.
+    fn symbolize(&self, pc: u32, exception: bool, caller: bool) -> Vec<Frame> {
+        let linked = self.linked(pc);
+        // The return address points to the instruction after the call.
+        let probe = if caller {
+            linked.wrapping_sub(1)
+        } else {
+            linked
+        };
+        let mut frames = Vec::new();
+
+        if let Some(context) = &self.context {
+            if let Ok(mut iter) = context.find_frames(probe.into()).skip_all_loads() {
+                while let Ok(Some(frame)) = iter.next() {
+                    let function = frame
+                        .function
+                        .as_ref()
+                        .and_then(|f| f.raw_name().ok())
+                        .map(|name| crate::elf::demangle(&name));
+                    let (file, line, column) = match frame.location {
+                        Some(location) => (
+                            location.file.map(str::to_owned),
+                            location.line,
+                            location.column,
+                        ),
+                        None => (None, None, None),
+                    };
+                    frames.push(Frame {
+                        pc,
+                        function,
+                        file,
+                        line,
+                        column,
+                        inlined: true,
+                        exception: false,
+                    });
+                }
+            }
+        }
+
+        match frames.last_mut() {
+            Some(last) => last.inlined = false,
+            None => frames.push(Frame {
+                pc,
+                function: None,
+                file: None,
+                line: None,
+                column: None,
+                inlined: false,
+                exception: false,
+            }),
+        }
+        frames[0].exception = exception;
+        let outermost = frames.last_mut().expect("no frames");
+        if outermost.function.is_none() {
+            outermost.function = self
+                .symbols
+                .lookup(probe)
+                .map(|symbol| symbol.demangled.clone());
+        }
+        frames
+    }
+
+    /// The innermost function containing runtime address `pc`, if known.
+    pub fn function(&self, pc: u32) -> Option<String> {
+        self.symbolize(pc, false, false)
+            .into_iter()
+            .find_map(|frame| frame.function)
+    }
+
+    /// How to find the caller's registers at runtime address `pc`.
+    fn unwind_row(&self, pc: u32) -> Option<gimli::UnwindTableRow<Reader>> {
+        let debug_frame = self.debug_frame.as_ref()?;
+        let bases = BaseAddresses::default();
+        let linked = self.linked(pc).into();
+        let fde = debug_frame
+            .fde_for_address(&bases, linked, DebugFrame::cie_from_offset)
+            .ok()?;
+        let mut context = UnwindContext::new();
+        fde.unwind_info_for_address(debug_frame, &bases, &mut context, linked)
+            .ok()
+            .cloned()
+    }
+}
+
+/// A stack frame, or a function inlined into a stack frame.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// The program counter, or the return address for callers.
+    pub pc: u32,
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    /// Whether the function was inlined into the next frame.
+    pub inlined: bool,
+    /// Whether the frame was interrupted by an exception, e.g. `pc` is the faulting instruction.
+    pub exception: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Backtrace {
+    /// The frames, starting with the innermost frame.
+    pub frames: Vec<Frame>,
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stack backtrace:")?;
+        for (index, frame) in self.frames.iter().enumerate() {
+            if frame.exception {
+                write!(f, "\n      <exception entry>")?;
+            }
+            let function = frame.function.as_deref().unwrap_or("<unknown>");
+            if frame.inlined {
+                write!(f, "\n{:>4}: {:>10} - {} (inlined)", index, "", function)?;
+            } else {
+                write!(f, "\n{:>4}: 0x{:08x} - {}", index, frame.pc, function)?;
+            }
+            if let Some(file) = &frame.file {
+                write!(f, "\n{:>10}at {}", "", file)?;
+                if let Some(line) = frame.line {
+                    write!(f, ":{}", line)?;
+                    if let Some(column) = frame.column {
+                        write!(f, ":{}", column)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Unwind the stack of the halted `core`.
+///
+/// Exception frames are unwound using the stacked registers, so the backtrace continues into the
+/// code that was interrupted, e.g. the instruction that caused a fault.
+pub fn unwind(core: &mut Core, debug_info: &DebugInfo) -> Result<Backtrace> {
+    let mut registers = [0u32; 16];
+    for (number, value) in registers.iter_mut().enumerate() {
+        *value = core.read_core_reg(number as u16)?;
+    }
+
+    let mut frames = Vec::new();
+    let mut exception = false;
+    let mut caller = false;
+
+    'frames: for depth in 0..MAX_FRAMES {
+        let pc = registers[PC as usize];
+        frames.extend(debug_info.symbolize(pc, exception, caller));
+
+        let sp = registers[SP as usize];
+        let (cfa, mut caller_registers) = match debug_info.unwind_row(pc) {
+            Some(row) => {
+                let cfa = match row.cfa() {
+                    CfaRule::RegisterAndOffset { register, offset } => {
+                        (i64::from(registers[usize::from(register.0)]) + offset) as u32
+                    }
+                    CfaRule::Expression(_) => {
+                        log::debug!("unsupported CFA expression at 0x{:08x}", pc);
+                        break;
+                    }
+                };
+                let mut caller_registers = registers;
+                for (number, value) in caller_registers.iter_mut().enumerate() {
+                    match row.register(Register(number as u16)) {
+                        // Callee saved registers that weren't saved are unchanged.
+                        RegisterRule::Undefined | RegisterRule::SameValue => {}
+                        RegisterRule::Offset(offset) => {
+                            *value = core.read_word_32((i64::from(cfa) + offset) as u32 as u64)?;
+                        }
+                        RegisterRule::ValOffset(offset) => {
+                            *value = (i64::from(cfa) + offset) as u32;
+                        }
+                        RegisterRule::Register(register) => {
+                            *value = registers[usize::from(register.0)];
+                        }
+                        rule => {
+                            log::debug!("unsupported register rule {:?} at 0x{:08x}", rule, pc);
+                            break 'frames;
+                        }
+                    }
+                }
+                (cfa, caller_registers)
+            }
+            // Without unwind info, assume the program stopped at the start of a function.
+            None if depth == 0 => (sp, registers),
+            None => {
+                log::debug!("no unwind info for 0x{:08x}", pc);
+                break;
+            }
+        };
+        caller_registers[SP as usize] = cfa;
+
+        let lr = caller_registers[LR as usize];
+        if lr & 0xFF00_0000 == 0xFF00_0000 {
+            // EXC_RETURN: the caller's registers were stacked on exception entry
+            if lr == 0xFFFF_FFFF {
+                break;
+            }
+            // Bit 2 selects the process stack, bit 4 is clear for frames including FP registers.
+            let frame = if lr & (1 << 2) == 0 {
+                cfa
+            } else {
+                let psp = core
+                    .registers()
+                    .psp()
+                    .ok_or_else(|| eyre!("core has no process stack pointer"))?;
+                core.read_core_reg(psp)?
+            };
+            let mut stacked = [0u32; 8];
+            core.read_32(frame.into(), &mut stacked)?;
+            let mut size = if lr & (1 << 4) != 0 { 0x20 } else { 0x68 };
+            let [r0, r1, r2, r3, r12, stacked_lr, stacked_pc, xpsr] = stacked;
+            caller_registers[..4].copy_from_slice(&[r0, r1, r2, r3]);
+            caller_registers[12] = r12;
+            caller_registers[LR as usize] = stacked_lr;
+            caller_registers[PC as usize] = stacked_pc;
+            if xpsr & (1 << 9) != 0 {
+                // The stack was realigned on exception entry.
+                size += 4;
+            }
+            caller_registers[SP as usize] = frame.wrapping_add(size);
+            exception = true;
+            caller = false;
+        } else {
+            caller_registers[PC as usize] = lr & !1;
+            if lr == 0 || (cfa <= sp && caller_registers == registers) {
+                break;
+            }
+            exception = false;
+            caller = true;
+        }
+
+        if caller_registers[PC as usize] == 0 {
+            break;
+        }
+        registers = caller_registers;
+    }
+
+    Ok(Backtrace { frames })
+}
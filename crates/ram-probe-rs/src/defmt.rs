@@ -1,5 +1,5 @@
 pub use defmt_decoder;
-use defmt_decoder::{DecodeError, Location, StreamDecoder, Table};
+use defmt_decoder::{DecodeError, Frame, Location, StreamDecoder, Table};
 use eyre::{bail, eyre, Context as _, Result};
 use std::collections::BTreeMap;
 use std::fmt;
@@ -51,61 +51,34 @@ pub struct DefmtDecoder<'opts> {
     stream: Box<dyn StreamDecoder + 'opts>,
     table: &'opts Table,
     locations: &'opts BTreeMap<u64, Location>,
-    target: &'opts str,
 }
 
 impl<'opts> DefmtDecoder<'opts> {
-    pub fn new(opts: &'opts DefmtInfo, target: &'opts str) -> Self {
+    pub fn new(opts: &'opts DefmtInfo) -> Self {
         let DefmtInfo { table, locations } = &opts;
         let stream = table.new_stream_decoder();
         Self {
             stream,
             table,
             locations,
-            target,
         }
     }
 }
 
 impl DefmtDecoder<'_> {
-    pub fn decode(&mut self, data: &[u8]) -> Result<()> {
+    /// Decode `data`, and call `f` with every complete frame and its location.
+    pub fn decode(
+        &mut self,
+        data: &[u8],
+        mut f: impl FnMut(&Frame<'_>, Option<&Location>) -> Result<()>,
+    ) -> Result<()> {
         self.stream.received(data);
 
         loop {
             match self.stream.decode() {
                 Ok(frame) => {
                     let loc = self.locations.get(&frame.index());
-
-                    let (mut file, mut line) = (None, None);
-                    if let Some(loc) = loc {
-                        file = Some(loc.file.display().to_string());
-                        line = Some(loc.line as u32);
-                    };
-
-                    let mut timestamp = String::new();
-                    if let Some(ts) = frame.display_timestamp() {
-                        timestamp = format!("{} ", ts);
-                    }
-
-                    log::logger().log(
-                        &log::Record::builder()
-                            .level(match frame.level() {
-                                Some(level) => match level.as_str() {
-                                    "trace" => log::Level::Trace,
-                                    "debug" => log::Level::Debug,
-                                    "info" => log::Level::Info,
-                                    "warn" => log::Level::Warn,
-                                    "error" => log::Level::Error,
-                                    _ => log::Level::Error,
-                                },
-                                None => log::Level::Info,
-                            })
-                            .file(file.as_deref())
-                            .line(line)
-                            .target(self.target)
-                            .args(format_args!("{}{}", timestamp, frame.display_message()))
-                            .build(),
-                    );
+                    f(&frame, loc)?;
                 }
                 Err(DecodeError::UnexpectedEof) => break,
                 Err(DecodeError::Malformed) => {
@@ -122,3 +95,37 @@ impl DefmtDecoder<'_> {
         Ok(())
     }
 }
+
+/// Log a frame with the `log` crate, using `target` as the log target.
+pub fn log_frame(frame: &Frame<'_>, loc: Option<&Location>, target: &str) {
+    let (mut file, mut line) = (None, None);
+    if let Some(loc) = loc {
+        file = Some(loc.file.display().to_string());
+        line = Some(loc.line as u32);
+    };
+
+    let mut timestamp = String::new();
+    if let Some(ts) = frame.display_timestamp() {
+        timestamp = format!("{} ", ts);
+    }
+
+    log::logger().log(
+        &log::Record::builder()
+            .level(match frame.level() {
+                Some(level) => match level.as_str() {
+                    "trace" => log::Level::Trace,
+                    "debug" => log::Level::Debug,
+                    "info" => log::Level::Info,
+                    "warn" => log::Level::Warn,
+                    "error" => log::Level::Error,
+                    _ => log::Level::Error,
+                },
+                None => log::Level::Info,
+            })
+            .file(file.as_deref())
+            .line(line)
+            .target(target)
+            .args(format_args!("{}{}", timestamp, frame.display_message()))
+            .build(),
+    );
+}
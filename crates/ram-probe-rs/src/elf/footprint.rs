@@ -0,0 +1,329 @@
+use super::{is_alloc, Parser};
+use crate::memory::{ram_placement, ram_ranges, region_name};
+use eyre::{bail, eyre, Result};
+use object::read::ObjectSection as _;
+use object::SectionKind;
+use probe_rs::Target;
+use std::fmt;
+use std::ops::Range;
+
+/// Symbols marking the lowest address of the stack, in order of preference.
+const STACK_LIMIT_SYMBOLS: &[&str] = &["_stack_end", "__StackLimit", "__stack_limit"];
+/// Absolute symbols holding the stack size, in order of preference.
+const STACK_SIZE_SYMBOLS: &[&str] = &["_Min_Stack_Size", "__stack_size__", "_stack_size"];
+/// Symbols marking the start of the heap, in order of preference.
+const HEAP_START_SYMBOLS: &[&str] = &["__sheap", "__HeapBase", "__heap_start__"];
+/// Symbols marking the end of the heap, in order of preference.
+const HEAP_END_SYMBOLS: &[&str] = &["__HeapLimit", "__heap_end__"];
+/// Absolute symbols holding the heap size, in order of preference.
+const HEAP_SIZE_SYMBOLS: &[&str] = &["_Min_Heap_Size", "__heap_size__", "_heap_size"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FootprintKind {
+    /// A section downloaded from the ELF file.
+    Loaded,
+    /// A `NOBITS` section zeroed at startup, e.g. `.bss`.
+    Zeroed,
+    /// A `NOBITS` section that isn't initialized, e.g. `.uninit`.
+    Uninit,
+    Heap,
+    Stack,
+}
+
+impl fmt::Display for FootprintKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Loaded => "loaded",
+            Self::Zeroed => "zeroed",
+            Self::Uninit => "uninit",
+            Self::Heap => "heap",
+            Self::Stack => "stack",
+        };
+        f.pad(kind)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FootprintEntry {
+    pub name: String,
+    pub kind: FootprintKind,
+    pub range: Range<u64>,
+}
+
+impl FootprintEntry {
+    pub fn size(&self) -> u64 {
+        self.range.end - self.range.start
+    }
+}
+
+/// The RAM used by a program at runtime, not only the bytes downloaded to the target.
+#[derive(Debug, Clone)]
+pub struct Footprint {
+    /// All allocated sections, the heap and the stack, sorted by address.
+    pub entries: Vec<FootprintEntry>,
+}
+
+impl Footprint {
+    /// The total number of bytes used.
+    pub fn total(&self) -> u64 {
+        self.entries.iter().map(FootprintEntry::size).sum()
+    }
+
+    /// The address range from the lowest to the highest used address.
+    pub fn extent(&self) -> Option<Range<u64>> {
+        let start = self.entries.iter().map(|e| e.range.start).min()?;
+        let end = self.entries.iter().map(|e| e.range.end).max()?;
+        Some(start..end)
+    }
+
+    /// All pairs of entries that overlap.
+    pub fn overlaps(&self) -> Vec<(&FootprintEntry, &FootprintEntry)> {
+        let mut overlaps = Vec::new();
+        for (i, a) in self.entries.iter().enumerate() {
+            for b in self.entries[i + 1..].iter() {
+                if a.range.start < b.range.end && b.range.start < a.range.end {
+                    overlaps.push((a, b));
+                }
+            }
+        }
+        overlaps
+    }
+
+    /// Move all entries by the (wrapping) `offset`, e.g. after relocation.
+    pub fn relocated(mut self, offset: u32) -> Self {
+        for entry in self.entries.iter_mut() {
+            let start = (entry.range.start as u32).wrapping_add(offset);
+            entry.range = u64::from(start)..u64::from(start) + entry.size();
+        }
+        self
+    }
+
+    /// Check the footprint fits in the target's RAM, and nothing overlaps.
+    pub fn check(&self, target: &Target) -> Result<()> {
+        let mut problems = Vec::new();
+
+        for (a, b) in self.overlaps() {
+            problems.push(format!(
+                "`{}` (0x{:08x}..0x{:08x}) overlaps `{}` (0x{:08x}..0x{:08x})",
+                a.name, a.range.start, a.range.end, b.name, b.range.start, b.range.end
+            ));
+        }
+
+        if self.entries.is_empty() {
+            bail!("program has no allocated sections");
+        }
+
+        // Each entry must be in a contiguous range of RAM, but the entries can be in different
+        // ranges, e.g. DTCM and AXI SRAM.
+        let ranges = ram_ranges(target);
+        let mut used = vec![0; ranges.len()];
+        for entry in self.entries.iter() {
+            if let Err(e) = ram_placement(target, entry.range.start, entry.range.end) {
+                problems.push(format!("`{}`: {}", entry.name, e));
+                continue;
+            }
+            if let Some(index) = ranges
+                .iter()
+                .position(|r| r.range.contains(&entry.range.start))
+            {
+                used[index] += entry.size();
+            }
+        }
+        for (range, used) in ranges.iter().zip(used).filter(|(_, used)| *used > 0) {
+            let available = range.range.end - range.range.start;
+            let names: Vec<_> = range.regions.iter().map(|r| region_name(r)).collect();
+            log::debug!(
+                "RAM footprint {} of {} bytes in `{}`",
+                used,
+                available,
+                names.join("`, `")
+            );
+            if used > available {
+                problems.push(format!(
+                    "total of {} bytes is larger than `{}` ({} bytes)",
+                    used,
+                    names.join("`, `"),
+                    available
+                ));
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!(
+                "program does not fit in RAM:\n  {}\n{}",
+                problems.join("\n  "),
+                self
+            );
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Footprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "RAM footprint:")?;
+        for entry in self.entries.iter() {
+            writeln!(
+                f,
+                "  0x{:08x}..0x{:08x} {:>8} bytes  {:<6}  {}",
+                entry.range.start,
+                entry.range.end,
+                entry.size(),
+                entry.kind,
+                entry.name
+            )?;
+        }
+        write!(f, "  total {} bytes", self.total())
+    }
+}
+
+impl<'data> Parser<'data> {
+    /// The RAM footprint of the program.
+    ///
+    /// The stack extends down from the initial stack pointer. Its size is taken from
+    /// `stack_size`, or from linker script symbols, or otherwise it extends down to the end of the
+    /// highest section below it in the same RAM range, or to the start of that range. The heap is
+    /// only included if `heap_size` is given, or linker script symbols define it.
+    pub fn footprint(
+        &self,
+        target: &Target,
+        stack_size: Option<u32>,
+        heap_size: Option<u32>,
+    ) -> Result<Footprint> {
+        let mut entries = Vec::new();
+
+        for (name, section) in self.named_sections() {
+            if !is_alloc(section.flags()) || section.size() == 0 {
+                continue;
+            }
+            let kind = match section.kind() {
+                SectionKind::UninitializedData | SectionKind::UninitializedTls => {
+                    if name.starts_with(".uninit") {
+                        FootprintKind::Uninit
+                    } else {
+                        FootprintKind::Zeroed
+                    }
+                }
+                _ => FootprintKind::Loaded,
+            };
+            entries.push(FootprintEntry {
+                name: name.to_owned(),
+                kind,
+                range: section.address()..section.address() + section.size(),
+            });
+        }
+        let sections_end = entries.iter().map(|e| e.range.end).max();
+
+        let heap_start = self
+            .first_symbol(HEAP_START_SYMBOLS)
+            .map(u64::from)
+            .or(sections_end);
+        let heap_end = match (heap_size, heap_start) {
+            (Some(size), Some(start)) => Some(start + u64::from(size)),
+            (None, Some(start)) => {
+                self.first_symbol(HEAP_END_SYMBOLS)
+                    .map(u64::from)
+                    .or_else(|| {
+                        self.first_symbol(HEAP_SIZE_SYMBOLS)
+                            .map(|size| start + u64::from(size))
+                    })
+            }
+            (_, None) => None,
+        };
+        if let (Some(start), Some(end)) = (heap_start, heap_end) {
+            if end > start {
+                entries.push(FootprintEntry {
+                    name: "<heap>".to_owned(),
+                    kind: FootprintKind::Heap,
+                    range: start..end,
+                });
+            }
+        }
+
+        if let Some(vector_table) = self.vector_table()? {
+            let top = u64::from(vector_table.initial_sp);
+            let bottom = match stack_size {
+                Some(size) => top.checked_sub(size.into()),
+                None => self
+                    .first_symbol(STACK_LIMIT_SYMBOLS)
+                    .map(u64::from)
+                    .or_else(|| {
+                        self.first_symbol(STACK_SIZE_SYMBOLS)
+                            .and_then(|size| top.checked_sub(size.into()))
+                    })
+                    .or_else(|| {
+                        // the stack can't extend past the RAM range it's in
+                        let ram = ram_ranges(target)
+                            .into_iter()
+                            .find(|r| r.range.start < top && top <= r.range.end);
+                        match ram {
+                            Some(ram) => {
+                                let floor = ram.range.start;
+                                let used_end = entries
+                                    .iter()
+                                    .map(|e| e.range.end)
+                                    .filter(|&end| floor < end && end <= top)
+                                    .max();
+                                Some(used_end.unwrap_or(floor))
+                            }
+                            None => {
+                                let used_end = entries.iter().map(|e| e.range.end).max()?;
+                                Some(used_end.min(top))
+                            }
+                        }
+                    }),
+            }
+            .ok_or_else(|| eyre!("stack is larger than the address space"))?;
+            entries.push(FootprintEntry {
+                name: "<stack>".to_owned(),
+                kind: FootprintKind::Stack,
+                range: bottom..top,
+            });
+        }
+
+        entries.sort_by_key(|e| (e.range.start, e.range.end));
+        Ok(Footprint { entries })
+    }
+
+    /// The address of the first symbol found.
+    fn first_symbol(&self, names: &[&str]) -> Option<u32> {
+        let symbols = self.symbols();
+        names
+            .iter()
+            .find_map(|name| symbols.get(name).map(|symbol| symbol.address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, range: Range<u64>) -> FootprintEntry {
+        FootprintEntry {
+            name: name.to_owned(),
+            kind: FootprintKind::Zeroed,
+            range,
+        }
+    }
+
+    #[test]
+    fn check_allows_separate_ram_ranges() {
+        let target = probe_rs::config::get_target_by_name("STM32H743ZITx").unwrap();
+        let footprint = Footprint {
+            entries: vec![
+                entry(".data", 0x2400_0000..0x2400_0100),
+                entry(".bss", 0x3000_0000..0x3000_0100),
+            ],
+        };
+        footprint.check(&target).unwrap();
+    }
+
+    #[test]
+    fn check_rejects_entry_crossing_a_gap() {
+        let target = probe_rs::config::get_target_by_name("STM32H743ZITx").unwrap();
+        let footprint = Footprint {
+            entries: vec![entry(".bss", 0x2400_0000..0x3000_0100)],
+        };
+        assert!(footprint.check(&target).is_err());
+    }
+}
@@ -1,24 +1,45 @@
+mod footprint;
+mod reloc;
+mod size;
+mod symbols;
 mod types;
+mod vectors;
 
 use eyre::{bail, eyre, Result};
+pub use footprint::{Footprint, FootprintEntry, FootprintKind};
 pub use object;
-use object::elf::{FileHeader32, PT_LOAD};
+use object::elf::{FileHeader32, PT_LOAD, SHF_ALLOC};
 use object::read::elf::{ElfFile32, ElfSection32, FileHeader as _, ProgramHeader as _};
 use object::read::Object as _;
 pub use object::read::ObjectSection;
-use object::{FileKind, LittleEndian, ObjectSymbol as _};
+use object::{FileKind, LittleEndian, ObjectSymbol as _, SectionFlags};
 use probe_rs::config::MemoryRange as _;
-use probe_rs::config::MemoryRegion;
 use probe_rs::Target;
+pub use reloc::Relocation;
+pub use size::{RegionUsage, SectionSize, SizeReport};
+use std::borrow::Cow;
+use std::cell::OnceCell;
 use std::convert::TryInto;
+pub use symbols::{demangle, Symbol, SymbolIndex};
 pub use types::*;
+pub use vectors::exception_name;
 
 pub type ElfSection<'data, 'file> = ElfSection32<'data, 'file, LittleEndian>;
 
+/// The RTT control block symbol used by `rtt-target` and SEGGER's RTT code.
+pub const DEFAULT_RTT_SYMBOL: &str = "_SEGGER_RTT";
+/// The vector table section used by `cortex-m-rt`.
+pub const DEFAULT_VECTOR_TABLE_SECTION: &str = ".vector_table";
+/// Vector table symbols used by C startup code, e.g. CMSIS, in order of preference.
+const VECTOR_TABLE_SYMBOLS: &[&str] = &["__isr_vector", "__Vectors", "_vector_table"];
+
 pub struct Parser<'data> {
     data: &'data [u8],
     header: FileHeader32<LittleEndian>,
     file: ElfFile32<'data, LittleEndian>,
+    symbols: OnceCell<SymbolIndex<'data>>,
+    rtt_symbol: String,
+    vector_table_section: String,
 }
 
 impl<'data> Parser<'data> {
@@ -29,10 +50,39 @@ impl<'data> Parser<'data> {
         }
         let header = *FileHeader32::<LittleEndian>::parse(data)?;
         let file = ElfFile32::<LittleEndian>::parse(data)?;
-        Ok(Self { data, header, file })
+        Ok(Self {
+            data,
+            header,
+            file,
+            symbols: OnceCell::new(),
+            rtt_symbol: DEFAULT_RTT_SYMBOL.to_owned(),
+            vector_table_section: DEFAULT_VECTOR_TABLE_SECTION.to_owned(),
+        })
+    }
+
+    /// Use a different RTT control block symbol than [`DEFAULT_RTT_SYMBOL`].
+    pub fn with_rtt_symbol(mut self, name: impl Into<String>) -> Self {
+        self.rtt_symbol = name.into();
+        self
+    }
+
+    /// Use a different vector table section than [`DEFAULT_VECTOR_TABLE_SECTION`].
+    pub fn with_vector_table_section(mut self, name: impl Into<String>) -> Self {
+        self.vector_table_section = name.into();
+        self
     }
 
     pub fn ram_loadable_segments(&self, target: &Target) -> Result<Segments<'data>> {
+        let segments = self.loadable_segments()?;
+        for (address, segment) in segments.iter() {
+            // Check that the section is in RAM
+            crate::memory::ensure_in_ram(target, *address, *address + segment.len() as u64)?;
+        }
+        Ok(segments)
+    }
+
+    /// All loadable segments at their linked physical address.
+    pub fn loadable_segments(&self) -> Result<Segments<'data>> {
         let endian = LittleEndian;
         let mut loadable_segments = Vec::new();
 
@@ -84,27 +134,9 @@ impl<'data> Parser<'data> {
                     segment_filesize
                 );
 
-                // Check that the section is in RAM
-                let start = paddr;
-                let end = start + segment_filesize;
-
-                let all_in_ram = target.memory_map.iter().any(|region| match region {
-                    MemoryRegion::Ram(r) => {
-                        let start_in_ram = r.range.start <= start;
-                        let end_in_ram = r.range.end >= end;
-                        start_in_ram && end_in_ram
-                    }
-                    MemoryRegion::Generic(_) => false,
-                    MemoryRegion::Nvm(_) => false,
-                });
-                if !all_in_ram {
-                    log::warn!("segment at 0x{:08x} is not in RAM", paddr);
-                    bail!("ELF contains non-RAM data");
-                }
-
                 let section_data =
                     &self.data[segment_range.start as usize..segment_range.end as usize];
-                loadable_segments.push((paddr, section_data));
+                loadable_segments.push((paddr, Cow::Borrowed(section_data)));
             } else {
                 log::warn!(
                     "segment at 0x{:08x} with no matching sections, skipping",
@@ -132,26 +164,125 @@ impl<'data> Parser<'data> {
             .filter_map(|section| section.name().ok().map(|name| (name, section)))
     }
 
+    /// The symbol index, built on first use.
+    pub fn symbols(&self) -> &SymbolIndex<'data> {
+        self.symbols.get_or_init(|| SymbolIndex::new(&self.file))
+    }
+
     pub fn rtt_address(&self) -> Option<u32> {
-        self.named_symbols().find_map(|(name, address)| {
-            if name == "_SEGGER_RTT" {
-                Some(address)
+        self.symbols()
+            .get(&self.rtt_symbol)
+            .map(|symbol| symbol.address)
+    }
+
+    /// The vector table, from the vector table section.
+    ///
+    /// If there is no such section, the table is found from a vector table symbol used by C
+    /// startup code (e.g. `__isr_vector` or `__Vectors`), or from the entry point.
+    pub fn vector_table(&self) -> Result<Option<VectorTable>> {
+        let vector_table = match self
+            .named_sections()
+            .find(|(name, _)| *name == self.vector_table_section)
+        {
+            Some((_, section)) => Some(parse_vector_table(section)?),
+            None => self.fallback_vector_table()?,
+        };
+        Ok(vector_table.map(|mut vector_table| {
+            vector_table.symbols = self.vector_symbols(&vector_table.entries);
+            vector_table
+        }))
+    }
+
+    fn fallback_vector_table(&self) -> Result<Option<VectorTable>> {
+        let symbols = self.symbols();
+        if let Some(symbol) = VECTOR_TABLE_SYMBOLS
+            .iter()
+            .find_map(|name| symbols.get(name))
+        {
+            log::debug!(
+                "vector table section `{}` not found, using symbol `{}`",
+                self.vector_table_section,
+                symbol.name
+            );
+            // Without a symbol size, only the system exceptions are parsed.
+            let size = if symbol.size >= 4 * 4 {
+                symbol.size
             } else {
-                None
+                16 * 4
+            };
+            let data = self
+                .alloc_data(symbol.address, size)
+                .ok_or_else(|| eyre!("vector table symbol `{}` has no data", symbol.name))?;
+            return parse_vector_table_data(symbol.address, data).map(Some);
+        }
+
+        // The reset handler is entry 1 of the vector table, which must be aligned for VTOR.
+        let entry = self.file.entry() as u32 | 1;
+        for (name, section) in self.named_sections() {
+            if !is_alloc(section.flags()) {
+                continue;
+            }
+            let Ok(data) = section.data() else {
+                continue;
+            };
+            let start = section.address() as u32;
+            let first = (start.wrapping_neg() % 128) as usize;
+            let found = (first..data.len().saturating_sub(16 * 4 - 1))
+                .step_by(128)
+                .find(|&offset| data[offset + 4..offset + 8] == entry.to_le_bytes());
+            if let Some(offset) = found {
+                let address = start + offset as u32;
+                log::debug!(
+                    "vector table section `{}` not found, using entry point 0x{:08x} in `{}` at 0x{:08x}",
+                    self.vector_table_section,
+                    entry,
+                    name,
+                    address
+                );
+                return parse_vector_table_data(address, &data[offset..offset + 16 * 4]).map(Some);
             }
+        }
+        Ok(None)
+    }
+
+    /// At most `len` bytes of the allocated section containing `address`.
+    fn alloc_data(&self, address: u32, len: u32) -> Option<&'data [u8]> {
+        let address = u64::from(address);
+        self.file.sections().find_map(|section| {
+            if !is_alloc(section.flags()) {
+                return None;
+            }
+            let offset = address.checked_sub(section.address())? as usize;
+            let data = section.data().ok()?;
+            let end = data.len().min(offset.checked_add(len as usize)?);
+            data.get(offset..end).filter(|data| !data.is_empty())
         })
     }
 
-    pub fn vector_table(&self) -> Result<Option<VectorTable>> {
-        self.named_sections()
-            .find_map(|(name, section)| {
-                if name == ".vector_table" {
-                    Some(parse_vector_table(section))
-                } else {
-                    None
-                }
+    /// The symbol names of vector table entries.
+    fn vector_symbols(&self, entries: &[u32]) -> Vec<Option<String>> {
+        let symbols = self.symbols();
+        entries
+            .iter()
+            .enumerate()
+            .map(|(index, &entry)| {
+                let symbol = match index {
+                    // the initial stack pointer is usually a linker script symbol
+                    0 => symbols.iter().find(|s| s.address == entry),
+                    _ if entry == 0 => None,
+                    _ => symbols.lookup(entry & !1),
+                };
+                symbol.map(|s| s.demangled.clone())
             })
-            .transpose()
+            .collect()
+    }
+}
+
+/// Whether a section occupies memory at runtime.
+fn is_alloc(flags: SectionFlags) -> bool {
+    match flags {
+        SectionFlags::Elf { sh_flags } => sh_flags & u64::from(SHF_ALLOC) != 0,
+        _ => false,
     }
 }
 
@@ -159,28 +290,40 @@ pub fn parse_vector_table(section: ElfSection32<'_, '_, LittleEndian>) -> Result
     let address = section.address() as u32;
     let size = section.size() as u32;
 
-    if size < 3 * 4 {
+    if size < 4 * 4 {
         bail!("vector table section is too small");
     }
 
     let data = section.data()?;
     assert!(
-        data.len() >= 3 * 4,
+        data.len() >= 4 * 4,
         "data length {} is smaller than size {}",
         data.len(),
         size
     );
-    // Entry 0: initial stack pointer
-    let initial_sp = u32::from_le_bytes(data[0..4].try_into().unwrap());
-    // Entry 1: reset handler
-    let reset = u32::from_le_bytes(data[4..8].try_into().unwrap());
-    // Entry 2:  hard fault handler
-    let hard_fault = u32::from_le_bytes(data[8..12].try_into().unwrap());
+    parse_vector_table_data(address, data)
+}
+
+/// Parse a vector table from raw bytes located at `address`.
+pub fn parse_vector_table_data(address: u32, data: &[u8]) -> Result<VectorTable> {
+    if data.len() < 4 * 4 {
+        bail!("vector table is too small");
+    }
+
+    let entries: Vec<u32> = data
+        .chunks_exact(4)
+        .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+        .collect();
 
     Ok(VectorTable {
         address,
-        initial_sp,
-        reset,
-        hard_fault,
+        // Entry 0: initial stack pointer
+        initial_sp: entries[0],
+        // Entry 1: reset handler
+        reset: entries[1],
+        // Entry 3: hard fault handler
+        hard_fault: entries[3],
+        symbols: vec![None; entries.len()],
+        entries,
     })
 }
@@ -0,0 +1,471 @@
+use super::{is_alloc, parse_vector_table_data, Parser, Segments, VectorTable};
+use eyre::{bail, eyre, Result};
+use object::elf::{
+    ET_DYN, ET_EXEC, PT_LOAD, R_ARM_ABS32, R_ARM_CALL, R_ARM_JUMP24, R_ARM_NONE, R_ARM_PREL31,
+    R_ARM_REL32, R_ARM_RELATIVE, R_ARM_TARGET1, R_ARM_THM_ALU_PREL_11_0, R_ARM_THM_JUMP19,
+    R_ARM_THM_JUMP24, R_ARM_THM_JUMP6, R_ARM_THM_MOVT_ABS, R_ARM_THM_MOVT_PREL,
+    R_ARM_THM_MOVW_ABS_NC, R_ARM_THM_MOVW_PREL_NC, R_ARM_THM_PC11, R_ARM_THM_PC12, R_ARM_THM_PC22,
+    R_ARM_THM_PC8, R_ARM_THM_PC9, R_ARM_V4BX,
+};
+use object::read::elf::{FileHeader as _, ProgramHeader as _};
+use object::read::{Object as _, ObjectSection as _, ObjectSymbol as _};
+use object::{LittleEndian, RelocationFlags, RelocationTarget, SectionIndex, SymbolSection};
+use probe_rs::Target;
+use std::convert::TryInto;
+
+/// A RAM image moved away from its linked address.
+#[derive(Debug, Clone)]
+pub struct Relocation<'data> {
+    /// The relocated segments.
+    pub segments: Segments<'data>,
+    /// The (wrapping) offset added to all addresses in the image.
+    pub offset: u32,
+}
+
+impl Relocation<'_> {
+    /// The relocated address of a linked address, e.g. the RTT control block.
+    pub fn address(&self, linked: u32) -> u32 {
+        linked.wrapping_add(self.offset)
+    }
+
+    /// Parse the relocated vector table, which has the same entries and symbols as the linked one.
+    pub fn vector_table(&self, linked: &VectorTable) -> Result<VectorTable> {
+        let address = self.address(linked.address);
+        let data = self
+            .segments
+            .read(address.into(), linked.size() as usize)
+            .ok_or_else(|| eyre!("vector table at 0x{:08x} is not in the image", address))?;
+        let mut vector_table = parse_vector_table_data(address, data)?;
+        vector_table.symbols = linked.symbols.clone();
+        Ok(vector_table)
+    }
+}
+
+/// A relocation entry that needs to be applied to the image.
+struct Fixup {
+    r_type: u32,
+    /// The linked address of the place to patch.
+    place: u32,
+    /// The section containing the place, for static relocations.
+    section: Option<SectionIndex>,
+    target: RelocationTarget,
+    /// Whether the relocation target moves with the image.
+    moves: bool,
+}
+
+impl<'data> Parser<'data> {
+    /// Relocate the RAM image to `load_address`, or the first RAM range large enough.
+    ///
+    /// The ELF must have been linked with relocations kept (`--emit-relocs`) or as position
+    /// independent executable (`-pie`). All allocated sections move together, so only absolute
+    /// references into the image are adjusted. The initial stack pointer is moved by the same
+    /// offset.
+    pub fn relocate(
+        &self,
+        target: &Target,
+        load_address: Option<u32>,
+    ) -> Result<Relocation<'data>> {
+        let endian = LittleEndian;
+
+        let e_type = self.header.e_type(endian);
+        if e_type != ET_EXEC && e_type != ET_DYN {
+            bail!("only linked ELF files can be relocated");
+        }
+        for segment in self.header.program_headers(endian, self.data)? {
+            if segment.p_type(endian) == PT_LOAD
+                && segment.p_vaddr(endian) != segment.p_paddr(endian)
+            {
+                bail!(
+                    "segment at 0x{:08x} has a different load address, cannot relocate",
+                    segment.p_vaddr(endian)
+                );
+            }
+        }
+
+        let mut segments = self.loadable_segments()?;
+        let (start, _) = segments
+            .iter()
+            .next()
+            .ok_or_else(|| eyre!("ELF has no loadable segments"))?;
+        let start = *start as u32;
+        let vector_table = self
+            .vector_table()?
+            .ok_or_else(|| eyre!("vector table not found"))?;
+
+        // The image extent includes uninitialized data and the stack above it.
+        let mut end = segments
+            .iter()
+            .map(|(address, data)| *address + data.len() as u64)
+            .max()
+            .unwrap_or_default();
+        let mut align = u64::from(vector_table.alignment());
+        for section in self.file.sections() {
+            if is_alloc(section.flags()) && section.size() > 0 {
+                end = end.max(section.address() + section.size());
+                align = align.max(section.align());
+            }
+        }
+        end = end.max(vector_table.initial_sp.into());
+        let span = end - u64::from(start);
+
+        let load_address = match load_address {
+            Some(address) => {
+                if u64::from(address) % align != 0 {
+                    bail!(
+                        "load address 0x{:08x} is not aligned to {} bytes",
+                        address,
+                        align
+                    );
+                }
+                address
+            }
+            None => crate::memory::ram_ranges(target)
+                .iter()
+                .find_map(|ram| {
+                    let address = ram.range.start.next_multiple_of(align);
+                    (address + span <= ram.range.end).then_some(address as u32)
+                })
+                .ok_or_else(|| eyre!("no RAM region is large enough for {} bytes", span))?,
+        };
+        let offset = load_address.wrapping_sub(start);
+        log::info!(
+            "relocating image from 0x{:08x} to 0x{:08x} ({} bytes)",
+            start,
+            load_address,
+            span
+        );
+
+        let fixups = self.fixups()?;
+        if fixups.is_empty() {
+            bail!("ELF has no relocations, link with `--emit-relocs` or `-pie`");
+        }
+        log::debug!("applying {} relocations", fixups.len());
+
+        // `MOVT` relocations need the linked value of their `MOVW` pair.
+        let linked = segments.clone();
+        for fixup in fixups.iter() {
+            match fixup.r_type {
+                R_ARM_ABS32 | R_ARM_TARGET1 | R_ARM_RELATIVE if fixup.moves => {
+                    let value = read_u32(&segments, fixup.place)?;
+                    write_u32(&mut segments, fixup.place, value.wrapping_add(offset))?;
+                }
+                R_ARM_THM_MOVW_ABS_NC if fixup.moves => {
+                    let value = read_thumb_imm16(&segments, fixup.place)?;
+                    let value = value.wrapping_add(offset as u16);
+                    write_thumb_imm16(&mut segments, fixup.place, value)?;
+                }
+                R_ARM_THM_MOVT_ABS if fixup.moves => {
+                    relocate_movt(&mut segments, &linked, &fixups, fixup, offset)?;
+                }
+                R_ARM_ABS32 | R_ARM_TARGET1 | R_ARM_THM_MOVW_ABS_NC | R_ARM_THM_MOVT_ABS => {
+                    // absolute reference outside the image, e.g. a peripheral
+                }
+                R_ARM_NONE | R_ARM_V4BX => {}
+                R_ARM_REL32
+                | R_ARM_PREL31
+                | R_ARM_CALL
+                | R_ARM_JUMP24
+                | R_ARM_THM_PC22
+                | R_ARM_THM_JUMP24
+                | R_ARM_THM_JUMP19
+                | R_ARM_THM_JUMP6
+                | R_ARM_THM_PC11
+                | R_ARM_THM_PC9
+                | R_ARM_THM_PC8
+                | R_ARM_THM_PC12
+                | R_ARM_THM_ALU_PREL_11_0
+                | R_ARM_THM_MOVW_PREL_NC
+                | R_ARM_THM_MOVT_PREL => {
+                    if !fixup.moves {
+                        bail!(
+                            "cannot relocate PC-relative reference at 0x{:08x} to a fixed address",
+                            fixup.place
+                        );
+                    }
+                }
+                r_type => bail!(
+                    "unsupported relocation type {} at 0x{:08x}",
+                    r_type,
+                    fixup.place
+                ),
+            }
+        }
+
+        // The initial stack pointer is a linker script expression, and has no relocation.
+        write_u32(
+            &mut segments,
+            vector_table.address,
+            vector_table.initial_sp.wrapping_add(offset),
+        )?;
+
+        for (address, _) in segments.0.iter_mut() {
+            *address = u64::from((*address as u32).wrapping_add(offset));
+        }
+        for (address, segment) in segments.iter() {
+            crate::memory::ensure_in_ram(target, *address, *address + segment.len() as u64)?;
+        }
+        let initial_sp = u64::from(vector_table.initial_sp.wrapping_add(offset));
+        let first_word = initial_sp.checked_sub(4).ok_or_else(|| {
+            eyre!(
+                "relocated initial stack pointer 0x{:08x} has no room for the stack",
+                initial_sp
+            )
+        })?;
+        crate::memory::ensure_in_ram(target, first_word, initial_sp)?;
+
+        Ok(Relocation { segments, offset })
+    }
+
+    /// All static and dynamic relocations that patch allocated sections.
+    fn fixups(&self) -> Result<Vec<Fixup>> {
+        let mut fixups = Vec::new();
+
+        for section in self.file.sections() {
+            if !is_alloc(section.flags()) || section.file_range().is_none() {
+                continue;
+            }
+            for (place, relocation) in section.relocations() {
+                let RelocationFlags::Elf { r_type } = relocation.flags() else {
+                    continue;
+                };
+                fixups.push(Fixup {
+                    r_type,
+                    place: place as u32,
+                    section: Some(section.index()),
+                    target: relocation.target(),
+                    moves: self.moves_with_image(relocation.target())?,
+                });
+            }
+        }
+
+        if let Some(relocations) = self.file.dynamic_relocations() {
+            for (place, relocation) in relocations {
+                let RelocationFlags::Elf { r_type } = relocation.flags() else {
+                    continue;
+                };
+                let moves = match r_type {
+                    R_ARM_RELATIVE => true,
+                    _ => self.moves_with_image(relocation.target())?,
+                };
+                fixups.push(Fixup {
+                    r_type,
+                    place: place as u32,
+                    section: None,
+                    target: relocation.target(),
+                    moves,
+                });
+            }
+        }
+
+        Ok(fixups)
+    }
+
+    /// Whether the relocation target is in an allocated section, and so moves with the image.
+    fn moves_with_image(&self, target: RelocationTarget) -> Result<bool> {
+        let section_index = match target {
+            RelocationTarget::Section(index) => index,
+            RelocationTarget::Symbol(index) => match self.file.symbol_by_index(index)?.section() {
+                SymbolSection::Section(index) => index,
+                _ => return Ok(false),
+            },
+            _ => return Ok(false),
+        };
+        let section = self.file.section_by_index(section_index)?;
+        Ok(is_alloc(section.flags()))
+    }
+}
+
+/// Add `offset` to the upper half of a `MOVT` relocation, carrying from its `MOVW` pair.
+fn relocate_movt(
+    segments: &mut Segments,
+    linked: &Segments,
+    fixups: &[Fixup],
+    movt: &Fixup,
+    offset: u32,
+) -> Result<()> {
+    let high = read_thumb_imm16(segments, movt.place)?;
+    let low = movw_pair(linked, fixups, movt)?;
+    let value = (u32::from(high) << 16) | u32::from(low);
+    let high = (value.wrapping_add(offset) >> 16) as u16;
+    write_thumb_imm16(segments, movt.place, high)
+}
+
+/// The lower half of the linked value of a `MOVT` relocation.
+///
+/// `REL` relocations don't keep the addend, so this is taken from the `MOVW` that sets the same
+/// register to the same target, closest before the `MOVT` in the same section.
+fn movw_pair(segments: &Segments, fixups: &[Fixup], movt: &Fixup) -> Result<u16> {
+    let rd = read_thumb_rd(segments, movt.place)?;
+    let same_register = |f: &Fixup| {
+        f.target == movt.target
+            && f.section == movt.section
+            && f.place < movt.place
+            && read_thumb_rd(segments, f.place).is_ok_and(|r| r == rd)
+    };
+    let movw = fixups
+        .iter()
+        .filter(|f| f.r_type == R_ARM_THM_MOVW_ABS_NC && same_register(f))
+        .max_by_key(|f| f.place)
+        .ok_or_else(|| {
+            eyre!(
+                "no `MOVW` pair found for the `MOVT` relocation at 0x{:08x}",
+                movt.place
+            )
+        })?;
+    // another `MOVT` of the same register in between would have used the `MOVW` already
+    if fixups
+        .iter()
+        .any(|f| f.r_type == R_ARM_THM_MOVT_ABS && f.place > movw.place && same_register(f))
+    {
+        bail!(
+            "the `MOVW` pair of the `MOVT` relocation at 0x{:08x} is ambiguous",
+            movt.place
+        );
+    }
+    read_thumb_imm16(segments, movw.place)
+}
+
+fn read_u32(segments: &Segments, address: u32) -> Result<u32> {
+    let data = segments
+        .read(address.into(), 4)
+        .ok_or_else(|| eyre!("relocation at 0x{:08x} is outside of the image", address))?;
+    Ok(u32::from_le_bytes(data.try_into().unwrap()))
+}
+
+fn write_u32(segments: &mut Segments, address: u32, value: u32) -> Result<()> {
+    segments.patch(address.into(), &value.to_le_bytes())
+}
+
+/// Read the 16-bit immediate of a Thumb-2 `MOVW`/`MOVT` instruction.
+fn read_thumb_imm16(segments: &Segments, address: u32) -> Result<u16> {
+    let data = segments
+        .read(address.into(), 4)
+        .ok_or_else(|| eyre!("relocation at 0x{:08x} is outside of the image", address))?;
+    let hw1 = u16::from_le_bytes([data[0], data[1]]);
+    let hw2 = u16::from_le_bytes([data[2], data[3]]);
+    let imm4 = hw1 & 0xf;
+    let i = (hw1 >> 10) & 1;
+    let imm3 = (hw2 >> 12) & 7;
+    let imm8 = hw2 & 0xff;
+    Ok((imm4 << 12) | (i << 11) | (imm3 << 8) | imm8)
+}
+
+/// Read the destination register of a Thumb-2 `MOVW`/`MOVT` instruction.
+fn read_thumb_rd(segments: &Segments, address: u32) -> Result<u16> {
+    let data = segments
+        .read(address.into(), 4)
+        .ok_or_else(|| eyre!("relocation at 0x{:08x} is outside of the image", address))?;
+    let hw2 = u16::from_le_bytes([data[2], data[3]]);
+    Ok((hw2 >> 8) & 0xf)
+}
+
+/// Write the 16-bit immediate of a Thumb-2 `MOVW`/`MOVT` instruction.
+fn write_thumb_imm16(segments: &mut Segments, address: u32, value: u16) -> Result<()> {
+    let data = segments
+        .read(address.into(), 4)
+        .ok_or_else(|| eyre!("relocation at 0x{:08x} is outside of the image", address))?;
+    let hw1 = u16::from_le_bytes([data[0], data[1]]);
+    let hw2 = u16::from_le_bytes([data[2], data[3]]);
+    let hw1 = (hw1 & !0x040f) | ((value >> 12) & 0xf) | (((value >> 11) & 1) << 10);
+    let hw2 = (hw2 & !0x70ff) | (((value >> 8) & 7) << 12) | (value & 0xff);
+    let [b0, b1] = hw1.to_le_bytes();
+    let [b2, b3] = hw2.to_le_bytes();
+    segments.patch(address.into(), &[b0, b1, b2, b3])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    #[test]
+    fn relocated_vector_table_keeps_all_entries() {
+        let linked_entries: [u32; 4] = [0x2000_1000, 0x2000_0101, 0x2000_0103, 0x2000_0105];
+        let mut linked_entries = linked_entries.to_vec();
+        linked_entries.resize(16, 0);
+        let data: Vec<u8> = linked_entries
+            .iter()
+            .flat_map(|e| e.to_le_bytes())
+            .collect();
+        let mut linked = parse_vector_table_data(0x2000_0000, &data).unwrap();
+        linked.symbols[1] = Some("Reset".to_owned());
+
+        // the relocations moved the stack pointer and the handlers by the offset
+        let offset = 0x1000;
+        let relocated: Vec<u8> = linked_entries
+            .iter()
+            .map(|&e| if e == 0 { 0 } else { e + offset })
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        let relocation = Relocation {
+            segments: Segments(vec![(0x2000_1000, Cow::Owned(relocated))]),
+            offset,
+        };
+
+        let vector_table = relocation.vector_table(&linked).unwrap();
+        assert_eq!(vector_table.address, 0x2000_1000);
+        assert_eq!(vector_table.initial_sp, 0x2000_2000);
+        assert_eq!(vector_table.reset, 0x2000_1101);
+        assert_eq!(vector_table.hard_fault, 0x2000_1105);
+        assert_eq!(vector_table.entries.len(), 16);
+        assert_eq!(vector_table.symbols[1].as_deref(), Some("Reset"));
+    }
+
+    #[test]
+    fn interleaved_movw_movt_pairs_carry_separately() {
+        // movw r0, #0xfff0; movw r1, #0x0010; movt r1, #0x2000; movt r0, #0x2000
+        let instructions: [(u16, u16, u16); 4] = [
+            (0xf240, 0, 0xfff0),
+            (0xf240, 1, 0x0010),
+            (0xf2c0, 1, 0x2000),
+            (0xf2c0, 0, 0x2000),
+        ];
+        let mut linked = Segments(vec![(0x2000_0000, Cow::Owned(vec![0; 16]))]);
+        for (place, &(hw1, rd, imm)) in (0x2000_0000..).step_by(4).zip(instructions.iter()) {
+            let [b0, b1] = hw1.to_le_bytes();
+            let [b2, b3] = (rd << 8).to_le_bytes();
+            linked.patch(place.into(), &[b0, b1, b2, b3]).unwrap();
+            write_thumb_imm16(&mut linked, place, imm).unwrap();
+        }
+        // both pairs reference the same section symbol, with different addends
+        let r_types = [
+            R_ARM_THM_MOVW_ABS_NC,
+            R_ARM_THM_MOVW_ABS_NC,
+            R_ARM_THM_MOVT_ABS,
+            R_ARM_THM_MOVT_ABS,
+        ];
+        let fixups: Vec<Fixup> = r_types
+            .into_iter()
+            .zip((0x2000_0000..).step_by(4))
+            .map(|(r_type, place)| Fixup {
+                r_type,
+                place,
+                section: Some(SectionIndex(1)),
+                target: RelocationTarget::Section(SectionIndex(2)),
+                moves: true,
+            })
+            .collect();
+
+        let mut segments = linked.clone();
+        for movt in &fixups[2..] {
+            relocate_movt(&mut segments, &linked, &fixups, movt, 0x20).unwrap();
+        }
+        // 0x2000_0010 + 0x20 stays below 0x2001_0000, 0x2000_fff0 + 0x20 carries
+        assert_eq!(read_thumb_imm16(&segments, 0x2000_0008).unwrap(), 0x2000);
+        assert_eq!(read_thumb_imm16(&segments, 0x2000_000c).unwrap(), 0x2001);
+    }
+
+    #[test]
+    fn movt_without_movw_pair_fails() {
+        let mut linked = Segments(vec![(0x2000_0000, Cow::Owned(vec![0; 4]))]);
+        linked.patch(0x2000_0000, &[0xc0, 0xf2, 0, 0]).unwrap();
+        let movt = Fixup {
+            r_type: R_ARM_THM_MOVT_ABS,
+            place: 0x2000_0000,
+            section: Some(SectionIndex(1)),
+            target: RelocationTarget::Section(SectionIndex(2)),
+            moves: true,
+        };
+        assert!(movw_pair(&linked, std::slice::from_ref(&movt), &movt).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use super::{is_alloc, FootprintKind, Parser};
+use crate::memory::{ram_regions, region_name};
+use eyre::Result;
+use object::read::ObjectSection as _;
+use object::SectionKind;
+use probe_rs::Target;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionSize {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+    /// A short description of the section contents, e.g. `text` or `bss`.
+    pub kind: &'static str,
+    /// Whether the section occupies memory at runtime.
+    pub alloc: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RegionUsage {
+    pub name: String,
+    pub start: u64,
+    pub end: u64,
+    /// The number of bytes used by sections, the heap and the stack.
+    pub used: u64,
+}
+
+impl RegionUsage {
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+/// Section sizes and RAM usage of a program, similar to `cargo size`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeReport {
+    pub sections: Vec<SectionSize>,
+    /// The usage of every RAM region in the target's memory map.
+    pub regions: Vec<RegionUsage>,
+    pub stack: Option<u64>,
+    pub heap: Option<u64>,
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .sections
+            .iter()
+            .map(|s| s.name.len())
+            .max()
+            .unwrap_or_default()
+            .max("section".len());
+
+        writeln!(
+            f,
+            "{:<width$}  {:>10}  {:>10}  type",
+            "section", "addr", "size"
+        )?;
+        for section in self.sections.iter() {
+            writeln!(
+                f,
+                "{:<width$}  0x{:08x}  {:>10}  {}",
+                section.name, section.address, section.size, section.kind
+            )?;
+        }
+        if let Some(stack) = self.stack {
+            writeln!(f, "{:<width$}  {:>10}  {:>10}", "<stack>", "", stack)?;
+        }
+        if let Some(heap) = self.heap {
+            writeln!(f, "{:<width$}  {:>10}  {:>10}", "<heap>", "", heap)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "RAM region usage:")?;
+        for region in self.regions.iter() {
+            let percent = if region.size() > 0 {
+                region.used as f64 * 100.0 / region.size() as f64
+            } else {
+                0.0
+            };
+            writeln!(
+                f,
+                "  {:<16} 0x{:08x}..0x{:08x} {:>10} / {:>10} bytes ({:5.1}%)",
+                region.name,
+                region.start,
+                region.end,
+                region.used,
+                region.size(),
+                percent
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<'data> Parser<'data> {
+    /// The size of every section, and the usage of each RAM region of the target.
+    ///
+    /// RAM usage is based on the [`footprint`](Self::footprint), so includes the stack and heap.
+    pub fn size_report(
+        &self,
+        target: &Target,
+        stack_size: Option<u32>,
+        heap_size: Option<u32>,
+    ) -> Result<SizeReport> {
+        let sections = self
+            .named_sections()
+            .filter(|(name, _)| !name.is_empty())
+            .map(|(name, section)| SectionSize {
+                name: name.to_owned(),
+                address: section.address(),
+                size: section.size(),
+                kind: section_kind(section.kind()),
+                alloc: is_alloc(section.flags()),
+            })
+            .collect();
+
+        let footprint = self.footprint(target, stack_size, heap_size)?;
+        let size_of = |kind| {
+            footprint
+                .entries
+                .iter()
+                .find(|e| e.kind == kind)
+                .map(|e| e.size())
+        };
+
+        let regions = ram_regions(target)
+            .map(|region| {
+                let used = footprint
+                    .entries
+                    .iter()
+                    .map(|e| {
+                        let start = e.range.start.max(region.range.start);
+                        let end = e.range.end.min(region.range.end);
+                        end.saturating_sub(start)
+                    })
+                    .sum();
+                RegionUsage {
+                    name: region_name(region).to_owned(),
+                    start: region.range.start,
+                    end: region.range.end,
+                    used,
+                }
+            })
+            .collect();
+
+        Ok(SizeReport {
+            sections,
+            regions,
+            stack: size_of(FootprintKind::Stack),
+            heap: size_of(FootprintKind::Heap),
+        })
+    }
+}
+
+fn section_kind(kind: SectionKind) -> &'static str {
+    match kind {
+        SectionKind::Text => "text",
+        SectionKind::Data | SectionKind::Tls => "data",
+        SectionKind::ReadOnlyData
+        | SectionKind::ReadOnlyDataWithRel
+        | SectionKind::ReadOnlyString => "rodata",
+        SectionKind::UninitializedData | SectionKind::UninitializedTls => "bss",
+        SectionKind::Debug => "debug",
+        SectionKind::Note => "note",
+        SectionKind::Metadata | SectionKind::Linker | SectionKind::OtherString => "metadata",
+        _ => "other",
+    }
+}
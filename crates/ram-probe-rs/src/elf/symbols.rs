@@ -0,0 +1,125 @@
+use object::read::elf::ElfFile32;
+use object::read::{Object as _, ObjectSymbol as _};
+use object::{LittleEndian, SymbolKind};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct Symbol<'data> {
+    /// The (mangled) symbol name.
+    pub name: &'data str,
+    /// The demangled Rust or C++ symbol name, or the symbol name if it isn't mangled.
+    pub demangled: String,
+    /// The symbol address. For functions, the Thumb bit is cleared.
+    pub address: u32,
+    pub size: u32,
+    pub kind: SymbolKind,
+}
+
+impl Symbol<'_> {
+    /// Whether `address` is inside the symbol.
+    pub fn contains(&self, address: u32) -> bool {
+        self.address <= address
+            && u64::from(address) < u64::from(self.address) + u64::from(self.size)
+    }
+}
+
+/// Symbol lookup by name and by address.
+#[derive(Debug, Clone)]
+pub struct SymbolIndex<'data> {
+    /// All named symbols, sorted by address.
+    symbols: Vec<Symbol<'data>>,
+    by_name: HashMap<&'data str, usize>,
+    by_demangled: HashMap<String, usize>,
+    max_size: u32,
+}
+
+impl<'data> SymbolIndex<'data> {
+    pub(super) fn new(file: &ElfFile32<'data, LittleEndian>) -> Self {
+        let mut symbols: Vec<_> = file
+            .symbols()
+            .filter_map(|symbol| {
+                let name = symbol.name().ok().filter(|name| !name.is_empty())?;
+                let kind = symbol.kind();
+                let mut address = symbol.raw_symbol().st_value.get(LittleEndian);
+                if kind == SymbolKind::Text {
+                    // clear the Thumb bit
+                    address &= !1;
+                }
+                Some(Symbol {
+                    name,
+                    demangled: demangle(name),
+                    address,
+                    size: symbol.size() as u32,
+                    kind,
+                })
+            })
+            .collect();
+        symbols.sort_by_key(|s| (s.address, s.name));
+
+        let mut by_name = HashMap::new();
+        let mut by_demangled = HashMap::new();
+        for (index, symbol) in symbols.iter().enumerate() {
+            by_name.entry(symbol.name).or_insert(index);
+            by_demangled
+                .entry(symbol.demangled.clone())
+                .or_insert(index);
+        }
+        let max_size = symbols.iter().map(|s| s.size).max().unwrap_or_default();
+
+        Self {
+            symbols,
+            by_name,
+            by_demangled,
+            max_size,
+        }
+    }
+
+    /// All symbols, sorted by address.
+    pub fn iter(&self) -> std::slice::Iter<'_, Symbol<'data>> {
+        self.symbols.iter()
+    }
+
+    /// All symbols of a kind, e.g. functions (`SymbolKind::Text`) or statics (`SymbolKind::Data`).
+    pub fn of_kind(&self, kind: SymbolKind) -> impl Iterator<Item = &Symbol<'data>> + '_ {
+        self.symbols.iter().filter(move |s| s.kind == kind)
+    }
+
+    /// Look up a symbol by its exact mangled or demangled name.
+    pub fn get(&self, name: &str) -> Option<&Symbol<'data>> {
+        self.by_name
+            .get(name)
+            .or_else(|| self.by_demangled.get(name))
+            .map(|index| &self.symbols[*index])
+    }
+
+    /// Look up the symbol that contains `address`, based on the symbol sizes.
+    ///
+    /// If several symbols contain the address, the smallest one is returned.
+    pub fn lookup(&self, address: u32) -> Option<&Symbol<'data>> {
+        let end = self.symbols.partition_point(|s| s.address <= address);
+        let min_address = address.saturating_sub(self.max_size);
+        self.symbols[..end]
+            .iter()
+            .rev()
+            .take_while(|s| s.address >= min_address)
+            .filter(|s| matches!(s.kind, SymbolKind::Text | SymbolKind::Data))
+            .filter(|s| s.contains(address))
+            .min_by_key(|s| s.size)
+    }
+}
+
+/// Demangle a Rust or C++ symbol name.
+pub fn demangle(name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        // alternate formatting omits the hash
+        return format!("{:#}", demangled);
+    }
+    if name.starts_with("_Z") {
+        if let Ok(symbol) = cpp_demangle::Symbol::new(name) {
+            if let Ok(demangled) = symbol.demangle(&Default::default()) {
+                return demangled;
+            }
+        }
+    }
+    name.to_owned()
+}
@@ -1,3 +1,5 @@
+use eyre::{bail, Result};
+use std::borrow::Cow;
 use std::fmt;
 
 #[repr(transparent)]
@@ -9,7 +11,7 @@ impl fmt::Debug for HexU32 {
     }
 }
 
-struct SegmentDebug<'a, 'data>(&'a (u64, &'data [u8]));
+struct SegmentDebug<'a, 'data>(&'a (u64, Cow<'data, [u8]>));
 
 impl fmt::Debug for SegmentDebug<'_, '_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -18,12 +20,33 @@ impl fmt::Debug for SegmentDebug<'_, '_> {
 }
 
 #[derive(Clone)]
-pub struct Segments<'data>(pub Vec<(u64, &'data [u8])>);
+pub struct Segments<'data>(pub Vec<(u64, Cow<'data, [u8]>)>);
 
 impl<'data> Segments<'data> {
-    pub fn iter(&self) -> std::slice::Iter<'_, (u64, &[u8])> {
+    pub fn iter(&self) -> std::slice::Iter<'_, (u64, Cow<'data, [u8]>)> {
         self.0.iter()
     }
+
+    /// The bytes at `address`, if they are inside a single segment.
+    pub fn read(&self, address: u64, len: usize) -> Option<&[u8]> {
+        self.0.iter().find_map(|(start, segment)| {
+            let offset = address.checked_sub(*start)? as usize;
+            segment.get(offset..offset.checked_add(len)?)
+        })
+    }
+
+    /// Overwrite the bytes at `address`, which must be inside a single segment.
+    pub fn patch(&mut self, address: u64, bytes: &[u8]) -> Result<()> {
+        let end = address + bytes.len() as u64;
+        for (start, segment) in self.0.iter_mut() {
+            if *start <= address && end <= *start + segment.len() as u64 {
+                let offset = (address - *start) as usize;
+                segment.to_mut()[offset..offset + bytes.len()].copy_from_slice(bytes);
+                return Ok(());
+            }
+        }
+        bail!("patch at 0x{:08x} is outside of the image", address);
+    }
 }
 
 impl fmt::Debug for Segments<'_> {
@@ -43,6 +66,10 @@ pub struct VectorTable {
     pub reset: u32,
     /// The hard fault handler address.
     pub hard_fault: u32,
+    /// All entries, indexed by exception number, starting with the initial stack pointer.
+    pub entries: Vec<u32>,
+    /// The symbol name of each entry, if known.
+    pub symbols: Vec<Option<String>>,
 }
 
 impl fmt::Debug for VectorTable {
@@ -52,6 +79,7 @@ impl fmt::Debug for VectorTable {
             .field("initial_sp", &HexU32(self.initial_sp))
             .field("reset", &HexU32(self.reset))
             .field("hard_fault", &HexU32(self.hard_fault))
+            .field("entries", &self.entries.len())
             .finish()
     }
 }
@@ -0,0 +1,122 @@
+use super::{Segments, VectorTable};
+use crate::memory::ram_ranges;
+use eyre::{bail, Result};
+use probe_rs::Target;
+use std::borrow::Cow;
+
+/// The number of system exception entries, including the initial stack pointer.
+const SYSTEM_EXCEPTIONS: usize = 16;
+
+/// The name of the vector table entry for exception number `index`.
+///
+/// Entries after the system exceptions are device interrupts, e.g. `IRQ0`.
+pub fn exception_name(index: usize) -> Cow<'static, str> {
+    let name = match index {
+        0 => "initial SP",
+        1 => "Reset",
+        2 => "NMI",
+        3 => "HardFault",
+        4 => "MemManage",
+        5 => "BusFault",
+        6 => "UsageFault",
+        7 => "SecureFault",
+        8..=10 | 13 => "Reserved",
+        11 => "SVCall",
+        12 => "DebugMonitor",
+        14 => "PendSV",
+        15 => "SysTick",
+        _ => return Cow::Owned(format!("IRQ{}", index - SYSTEM_EXCEPTIONS)),
+    };
+    Cow::Borrowed(name)
+}
+
+impl VectorTable {
+    /// The size of the vector table in bytes.
+    pub fn size(&self) -> u32 {
+        self.entries.len() as u32 * 4
+    }
+
+    /// The VTOR alignment required for the vector table.
+    ///
+    /// The table must be aligned to its size rounded up to a power of two, and at least 128 bytes.
+    pub fn alignment(&self) -> u32 {
+        self.size().next_power_of_two().max(128)
+    }
+
+    /// The display name of entry `index`, including its symbol if known.
+    pub fn entry_name(&self, index: usize) -> String {
+        match self.symbols.get(index).and_then(Option::as_deref) {
+            Some(symbol) => format!("{} (`{}`)", exception_name(index), symbol),
+            None => exception_name(index).into_owned(),
+        }
+    }
+
+    /// Check that the vector table is usable by the program loaded as `segments`.
+    ///
+    /// Handlers must be Thumb addresses inside the image, the initial stack pointer must be
+    /// 8-byte aligned and in RAM, and the table must meet the VTOR alignment rules.
+    pub fn check(&self, segments: &Segments, target: &Target) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if self.address % self.alignment() != 0 {
+            problems.push(format!(
+                "table address 0x{:08x} is not aligned to {} bytes, as required by VTOR for a table of {} bytes",
+                self.address,
+                self.alignment(),
+                self.size()
+            ));
+        }
+
+        if self.initial_sp % 8 != 0 {
+            problems.push(format!(
+                "{} 0x{:08x} is not 8-byte aligned",
+                self.entry_name(0),
+                self.initial_sp
+            ));
+        }
+        let sp = u64::from(self.initial_sp);
+        if !ram_ranges(target)
+            .iter()
+            .any(|r| r.range.start < sp && sp <= r.range.end)
+        {
+            problems.push(format!(
+                "{} 0x{:08x} is not in RAM",
+                self.entry_name(0),
+                self.initial_sp
+            ));
+        }
+
+        for (index, &entry) in self.entries.iter().enumerate().skip(1) {
+            if matches!(index, 8..=10 | 13) {
+                continue;
+            }
+            // Unused exceptions and interrupts may be zero, but the program can't run without a
+            // reset or hard fault handler.
+            if entry == 0 && !matches!(index, 1 | 3) {
+                continue;
+            }
+            if entry & 1 == 0 {
+                problems.push(format!(
+                    "{} 0x{:08x} does not have the Thumb bit set",
+                    self.entry_name(index),
+                    entry
+                ));
+            } else if segments.read(u64::from(entry & !1), 2).is_none() {
+                problems.push(format!(
+                    "{} 0x{:08x} is not in the loaded image",
+                    self.entry_name(index),
+                    entry
+                ));
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!(
+                "invalid vector table at 0x{:08x}:\n  {}",
+                self.address,
+                problems.join("\n  ")
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use crate::elf::Segments;
+use object::elf::{
+    FileHeader32, Ident, ProgramHeader32, EF_ARM_EABI_VER5, ELFCLASS32, ELFDATA2LSB, ELFMAG,
+    ELFOSABI_NONE, EM_ARM, ET_EXEC, EV_CURRENT, PF_R, PF_W, PF_X, PT_LOAD,
+};
+use object::endian::{LittleEndian, U16, U32};
+use object::pod::bytes_of;
+use std::io::{self, Write};
+use std::mem::size_of;
+
+/// Write the segments as a stripped ELF file, with one loadable segment per segment.
+pub(super) fn write_elf<W: Write>(
+    segments: &Segments,
+    entry: u32,
+    mut writer: W,
+) -> io::Result<()> {
+    let endian = LittleEndian;
+    let ehsize = size_of::<FileHeader32<LittleEndian>>();
+    let phentsize = size_of::<ProgramHeader32<LittleEndian>>();
+    let phnum = segments.0.len();
+
+    let u32_of = |value: u64| {
+        u32::try_from(value)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "image is larger than 32-bit"))
+    };
+
+    let header = FileHeader32 {
+        e_ident: Ident {
+            magic: ELFMAG,
+            class: ELFCLASS32,
+            data: ELFDATA2LSB,
+            version: EV_CURRENT,
+            os_abi: ELFOSABI_NONE,
+            abi_version: 0,
+            padding: [0; 7],
+        },
+        e_type: U16::new(endian, ET_EXEC),
+        e_machine: U16::new(endian, EM_ARM),
+        e_version: U32::new(endian, EV_CURRENT.into()),
+        e_entry: U32::new(endian, entry),
+        e_phoff: U32::new(endian, ehsize as u32),
+        e_shoff: U32::new(endian, 0),
+        e_flags: U32::new(endian, EF_ARM_EABI_VER5),
+        e_ehsize: U16::new(endian, ehsize as u16),
+        e_phentsize: U16::new(endian, phentsize as u16),
+        e_phnum: U16::new(endian, phnum as u16),
+        e_shentsize: U16::new(endian, 0),
+        e_shnum: U16::new(endian, 0),
+        e_shstrndx: U16::new(endian, 0),
+    };
+    writer.write_all(bytes_of(&header))?;
+
+    let mut offset = (ehsize + phnum * phentsize) as u64;
+    for (address, data) in segments.iter() {
+        let size = data.len() as u64;
+        let program_header = ProgramHeader32 {
+            p_type: U32::new(endian, PT_LOAD),
+            p_offset: U32::new(endian, u32_of(offset)?),
+            p_vaddr: U32::new(endian, u32_of(*address)?),
+            p_paddr: U32::new(endian, u32_of(*address)?),
+            p_filesz: U32::new(endian, u32_of(size)?),
+            p_memsz: U32::new(endian, u32_of(size)?),
+            p_flags: U32::new(endian, PF_R | PF_W | PF_X),
+            p_align: U32::new(endian, 1),
+        };
+        writer.write_all(bytes_of(&program_header))?;
+        offset += size;
+    }
+
+    for (_, data) in segments.iter() {
+        writer.write_all(data)?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,166 @@
+use super::{decode_hex, SegmentBuilder};
+use crate::elf::Segments;
+use eyre::{bail, eyre, Result};
+use std::io::{self, Write};
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Parse an Intel HEX image.
+pub fn parse_ihex(data: &[u8]) -> Result<Segments<'static>> {
+    // decoding slices the text by byte, so multi-byte characters are rejected up front
+    if !data.is_ascii() {
+        bail!("Intel HEX file is not ASCII");
+    }
+    let text = std::str::from_utf8(data).expect("ASCII is UTF-8");
+
+    let mut builder = SegmentBuilder::default();
+    let mut base: u64 = 0;
+    let mut finished = false;
+
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if finished {
+            bail!("line {}: data after end of file record", line_no);
+        }
+        let digits = line
+            .strip_prefix(':')
+            .ok_or_else(|| eyre!("line {}: missing start code `:`", line_no))?;
+        let record = decode_hex(line_no, digits)?;
+        if record.len() < 5 {
+            bail!("line {}: record is too short", line_no);
+        }
+
+        let checksum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if checksum != 0 {
+            bail!("line {}: checksum mismatch", line_no);
+        }
+
+        let len = record[0] as usize;
+        if record.len() != len + 5 {
+            bail!("line {}: record length mismatch", line_no);
+        }
+        let offset = u16::from_be_bytes([record[1], record[2]]);
+        let kind = record[3];
+        let payload = &record[4..4 + len];
+
+        match kind {
+            DATA => builder.push(base + u64::from(offset), payload),
+            END_OF_FILE => finished = true,
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS => {
+                if len != 2 {
+                    bail!("line {}: invalid extended address record", line_no);
+                }
+                let value = u64::from(u16::from_be_bytes([payload[0], payload[1]]));
+                base = if kind == EXTENDED_SEGMENT_ADDRESS {
+                    value << 4
+                } else {
+                    value << 16
+                };
+            }
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {
+                // the entry point is taken from the vector table
+                log::trace!("line {}: ignoring start address record", line_no);
+            }
+            _ => bail!("line {}: unknown record type 0x{:02x}", line_no, kind),
+        }
+    }
+
+    if !finished {
+        bail!("Intel HEX file has no end of file record");
+    }
+
+    builder.finish()
+}
+
+/// The number of data bytes per record when writing.
+const RECORD_SIZE: usize = 16;
+
+/// Write the segments as an Intel HEX image.
+pub(super) fn write_ihex<W: Write>(
+    segments: &Segments,
+    entry: u32,
+    mut writer: W,
+) -> io::Result<()> {
+    let mut upper = None;
+
+    for (address, data) in segments.iter() {
+        let mut address = *address;
+        for chunk in data.chunks(RECORD_SIZE) {
+            // records must not wrap a 64 KiB boundary
+            let offset = (address & 0xffff) as usize;
+            let (head, tail) = chunk.split_at(chunk.len().min(0x10000 - offset));
+            for part in [head, tail] {
+                if part.is_empty() {
+                    continue;
+                }
+                let this_upper = (address >> 16) as u16;
+                if upper != Some(this_upper) {
+                    write_record(
+                        &mut writer,
+                        EXTENDED_LINEAR_ADDRESS,
+                        0,
+                        &this_upper.to_be_bytes(),
+                    )?;
+                    upper = Some(this_upper);
+                }
+                write_record(&mut writer, DATA, address as u16, part)?;
+                address += part.len() as u64;
+            }
+        }
+    }
+
+    write_record(&mut writer, START_LINEAR_ADDRESS, 0, &entry.to_be_bytes())?;
+    write_record(&mut writer, END_OF_FILE, 0, &[])?;
+    writer.flush()
+}
+
+fn write_record<W: Write>(writer: &mut W, kind: u8, offset: u16, data: &[u8]) -> io::Result<()> {
+    let [hi, lo] = offset.to_be_bytes();
+    let mut record = vec![data.len() as u8, hi, lo, kind];
+    record.extend_from_slice(data);
+    let checksum = record
+        .iter()
+        .fold(0u8, |sum, b| sum.wrapping_add(*b))
+        .wrapping_neg();
+    record.push(checksum);
+
+    write!(writer, ":")?;
+    for b in record {
+        write!(writer, "{:02X}", b)?;
+    }
+    writeln!(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_malformed_records() {
+        for data in [
+            ":aéb",
+            "aabb",
+            ":0",
+            ":zz000000",
+            ":0100000000FE\n",
+            ":00000001FF\n:00000001FF\n",
+        ] {
+            assert!(parse_ihex(data.as_bytes()).is_err(), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn parses_data_records() {
+        let segments = parse_ihex(b":020010001234A8\n:00000001FF\n").unwrap();
+        assert_eq!(segments.read(0x10, 2), Some(&[0x12, 0x34][..]));
+    }
+}
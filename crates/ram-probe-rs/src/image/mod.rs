@@ -0,0 +1,189 @@
+mod elf;
+mod ihex;
+mod srec;
+
+use crate::elf::{parse_vector_table_data, Segments, VectorTable};
+use eyre::{bail, eyre, Result};
+pub use ihex::parse_ihex;
+use probe_rs::Target;
+pub use srec::parse_srec;
+use std::borrow::Cow;
+use std::io::{self, Read as _, Write};
+use std::path::Path;
+
+/// The file format of a program image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImageFormat {
+    /// ELF file
+    Elf,
+    /// Intel HEX file
+    Ihex,
+    /// Motorola S-record file
+    Srec,
+    /// Raw binary file, requires a base address
+    Bin,
+}
+
+impl ImageFormat {
+    /// Guess the image format from the file extension, defaulting to ELF.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hex" | "ihex" | "ihx") => Self::Ihex,
+            Some("srec" | "s19" | "s28" | "s37" | "mot") => Self::Srec,
+            Some("bin") => Self::Bin,
+            _ => Self::Elf,
+        }
+    }
+}
+
+/// Parse a raw binary image located at `base_address`.
+pub fn parse_bin(data: &[u8], base_address: u32) -> Segments<'_> {
+    Segments(vec![(base_address.into(), Cow::Borrowed(data))])
+}
+
+/// Parse a non-ELF image, and validate it is RAM-only.
+pub fn ram_loadable_segments<'data>(
+    data: &'data [u8],
+    format: ImageFormat,
+    base_address: Option<u32>,
+    target: &Target,
+) -> Result<Segments<'data>> {
+    let segments = match format {
+        ImageFormat::Elf => bail!("ELF files must be loaded with `elf::Parser`"),
+        ImageFormat::Ihex => parse_ihex(data)?,
+        ImageFormat::Srec => parse_srec(data)?,
+        ImageFormat::Bin => {
+            let base_address =
+                base_address.ok_or_else(|| eyre!("raw binary images require a base address"))?;
+            parse_bin(data, base_address)
+        }
+    };
+
+    for (address, segment) in segments.iter() {
+        log::debug!(
+            "found segment at 0x{:08x} ({} bytes)",
+            address,
+            segment.len()
+        );
+        crate::memory::ensure_in_ram(target, *address, *address + segment.len() as u64)?;
+    }
+
+    Ok(segments)
+}
+
+/// Parse the vector table from the start of the image.
+///
+/// The size of the table is unknown, so only the system exceptions are parsed.
+pub fn vector_table(segments: &Segments) -> Result<VectorTable> {
+    let (address, data) = segments
+        .iter()
+        .next()
+        .ok_or_else(|| eyre!("image is empty"))?;
+    let address = (*address)
+        .try_into()
+        .map_err(|_| eyre!("image start 0x{:08x} is not a 32-bit address", address))?;
+    parse_vector_table_data(address, &data[..data.len().min(16 * 4)])
+}
+
+impl Segments<'_> {
+    /// Write the segments to a file.
+    ///
+    /// `entry` is the reset handler address, which is recorded as the entry point if the format
+    /// supports it. Raw binary images are padded with `fill` between segments.
+    pub fn export<W: Write>(
+        &self,
+        format: ImageFormat,
+        entry: u32,
+        fill: u8,
+        writer: W,
+    ) -> io::Result<()> {
+        match format {
+            ImageFormat::Elf => elf::write_elf(self, entry, writer),
+            ImageFormat::Ihex => ihex::write_ihex(self, entry, writer),
+            ImageFormat::Srec => srec::write_srec(self, entry, writer),
+            ImageFormat::Bin => write_bin(self, fill, writer),
+        }
+    }
+}
+
+/// Write the segments as a single contiguous binary, starting at the lowest address.
+fn write_bin<W: Write>(segments: &Segments, fill: u8, mut writer: W) -> io::Result<()> {
+    let mut position = None;
+    for (address, data) in segments.iter() {
+        if let Some(position) = position {
+            let gap = address.checked_sub(position).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "segments are not sorted")
+            })?;
+            io::copy(&mut io::repeat(fill).take(gap), &mut writer)?;
+        }
+        writer.write_all(data)?;
+        position = Some(address + data.len() as u64);
+    }
+    writer.flush()
+}
+
+/// Collects data records into contiguous segments.
+#[derive(Default)]
+struct SegmentBuilder {
+    segments: Vec<(u64, Vec<u8>)>,
+}
+
+impl SegmentBuilder {
+    fn push(&mut self, address: u64, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if let Some((start, segment)) = self.segments.last_mut() {
+            if *start + segment.len() as u64 == address {
+                segment.extend_from_slice(data);
+                return;
+            }
+        }
+        self.segments.push((address, data.to_vec()));
+    }
+
+    fn finish(mut self) -> Result<Segments<'static>> {
+        self.segments.sort_by_key(|(addr, _)| *addr);
+
+        let mut merged: Vec<(u64, Vec<u8>)> = Vec::with_capacity(self.segments.len());
+        for (address, data) in self.segments {
+            if let Some((start, segment)) = merged.last_mut() {
+                let end = *start + segment.len() as u64;
+                if end > address {
+                    bail!("image data overlaps at 0x{:08x}", address);
+                }
+                if end == address {
+                    segment.extend_from_slice(&data);
+                    continue;
+                }
+            }
+            merged.push((address, data));
+        }
+
+        Ok(Segments(
+            merged
+                .into_iter()
+                .map(|(address, data)| (address, Cow::Owned(data)))
+                .collect(),
+        ))
+    }
+}
+
+/// Decode a string of hex digits into bytes.
+fn decode_hex(line: usize, digits: &str) -> Result<Vec<u8>> {
+    if digits.len() % 2 != 0 {
+        bail!("line {}: odd number of hex digits", line);
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| eyre!("line {}: invalid hex digits `{}`", line, &digits[i..i + 2]))
+        })
+        .collect()
+}
@@ -0,0 +1,136 @@
+use super::{decode_hex, SegmentBuilder};
+use crate::elf::Segments;
+use eyre::{bail, eyre, Result};
+use std::io::{self, Write};
+
+/// Parse a Motorola S-record image.
+pub fn parse_srec(data: &[u8]) -> Result<Segments<'static>> {
+    // decoding slices the text by byte, so multi-byte characters are rejected up front
+    if !data.is_ascii() {
+        bail!("S-record file is not ASCII");
+    }
+    let text = std::str::from_utf8(data).expect("ASCII is UTF-8");
+
+    let mut builder = SegmentBuilder::default();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut chars = line.chars();
+        if chars.next() != Some('S') {
+            bail!("line {}: missing start code `S`", line_no);
+        }
+        let kind = chars
+            .next()
+            .ok_or_else(|| eyre!("line {}: record is too short", line_no))?;
+        let record = decode_hex(line_no, chars.as_str())?;
+        if record.len() < 2 {
+            bail!("line {}: record is too short", line_no);
+        }
+
+        let count = record[0] as usize;
+        if record.len() != count + 1 {
+            bail!("line {}: record length mismatch", line_no);
+        }
+        let checksum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if checksum != 0xff {
+            bail!("line {}: checksum mismatch", line_no);
+        }
+
+        let address_len = match kind {
+            // header, data, count, termination
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => bail!("line {}: unknown record type S{}", line_no, kind),
+        };
+        if count < address_len + 1 {
+            bail!("line {}: record is too short", line_no);
+        }
+        let address = record[1..1 + address_len]
+            .iter()
+            .fold(0u64, |addr, b| (addr << 8) | u64::from(*b));
+        let payload = &record[1 + address_len..record.len() - 1];
+
+        match kind {
+            '1' | '2' | '3' => builder.push(address, payload),
+            '0' => log::trace!(
+                "line {}: header `{}`",
+                line_no,
+                String::from_utf8_lossy(payload)
+            ),
+            // the entry point is taken from the vector table
+            _ => log::trace!("line {}: ignoring S{} record", line_no, kind),
+        }
+    }
+
+    builder.finish()
+}
+
+/// The number of data bytes per record when writing.
+const RECORD_SIZE: usize = 16;
+
+/// Write the segments as a Motorola S-record image, using 32-bit addresses.
+pub(super) fn write_srec<W: Write>(
+    segments: &Segments,
+    entry: u32,
+    mut writer: W,
+) -> io::Result<()> {
+    write_record(&mut writer, '0', 0, b"ram-probe")?;
+
+    let mut count = 0u32;
+    for (address, data) in segments.iter() {
+        let mut address = *address as u32;
+        for chunk in data.chunks(RECORD_SIZE) {
+            write_record(&mut writer, '3', address, chunk)?;
+            address = address.wrapping_add(chunk.len() as u32);
+            count += 1;
+        }
+    }
+
+    if count <= 0xffff {
+        write_record(&mut writer, '5', count, &[])?;
+    }
+    write_record(&mut writer, '7', entry, &[])?;
+    writer.flush()
+}
+
+fn write_record<W: Write>(writer: &mut W, kind: char, address: u32, data: &[u8]) -> io::Result<()> {
+    let address = address.to_be_bytes();
+    let address = match kind {
+        '0' | '5' => &address[2..],
+        _ => &address[..],
+    };
+    let mut record = vec![(address.len() + data.len() + 1) as u8];
+    record.extend_from_slice(address);
+    record.extend_from_slice(data);
+    let checksum = !record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    record.push(checksum);
+
+    write!(writer, "S{}", kind)?;
+    for b in record {
+        write!(writer, "{:02X}", b)?;
+    }
+    writeln!(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_malformed_records() {
+        for data in ["S1aéb", "X1030000FC", "S", "S1zz", "S1040000FF\n"] {
+            assert!(parse_srec(data.as_bytes()).is_err(), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn parses_data_records() {
+        let segments = parse_srec(b"S10500101234A4\n").unwrap();
+        assert_eq!(segments.read(0x10, 2), Some(&[0x12, 0x34][..]));
+    }
+}
@@ -1,6 +1,9 @@
+pub mod backtrace;
 #[cfg(feature = "defmt")]
 pub mod defmt;
 pub mod elf;
+pub mod image;
+pub mod memory;
 pub mod run;
 pub mod session;
 
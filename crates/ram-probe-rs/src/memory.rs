@@ -0,0 +1,112 @@
+use eyre::{bail, Result};
+use probe_rs::config::{MemoryRegion, RamRegion};
+use probe_rs::Target;
+use std::ops::Range;
+
+/// All RAM regions in the target's memory map.
+pub fn ram_regions(target: &Target) -> impl Iterator<Item = &RamRegion> + '_ {
+    target.memory_map.iter().filter_map(|region| match region {
+        MemoryRegion::Ram(r) => Some(r),
+        MemoryRegion::Generic(_) => None,
+        MemoryRegion::Nvm(_) => None,
+    })
+}
+
+/// A contiguous range of RAM, made up of one or more adjacent RAM regions.
+#[derive(Debug, Clone)]
+pub struct RamRange<'a> {
+    pub range: Range<u64>,
+    /// The RAM regions in this range, sorted by address.
+    pub regions: Vec<&'a RamRegion>,
+}
+
+/// All RAM in the target's memory map, with adjacent RAM regions merged into one range.
+pub fn ram_ranges(target: &Target) -> Vec<RamRange<'_>> {
+    let mut regions: Vec<_> = ram_regions(target).collect();
+    regions.sort_by_key(|r| r.range.start);
+
+    let mut ranges: Vec<RamRange> = Vec::new();
+    for region in regions {
+        if let Some(last) = ranges.last_mut() {
+            if region.range.start <= last.range.end {
+                last.range.end = last.range.end.max(region.range.end);
+                last.regions.push(region);
+                continue;
+            }
+        }
+        ranges.push(RamRange {
+            range: region.range.clone(),
+            regions: vec![region],
+        });
+    }
+    ranges
+}
+
+/// The display name of a RAM region.
+pub fn region_name(region: &RamRegion) -> &str {
+    region.name.as_deref().unwrap_or("<unnamed>")
+}
+
+/// The RAM regions that the address range `start..end` is placed in.
+///
+/// The range may span several RAM regions, as long as they are adjacent.
+pub fn ram_placement(target: &Target, start: u64, end: u64) -> Result<Vec<&RamRegion>> {
+    let ranges = ram_ranges(target);
+
+    let range = match ranges
+        .iter()
+        .find(|r| r.range.start <= start && start < r.range.end)
+    {
+        Some(range) => range,
+        None => bail!("0x{:08x}..0x{:08x} is not in RAM", start, end),
+    };
+
+    if end > range.range.end {
+        let last = range.regions.last().expect("RAM range without regions");
+        match ranges.iter().find(|r| r.range.start >= range.range.end) {
+            Some(next) => bail!(
+                "0x{:08x}..0x{:08x} crosses non-contiguous memory: gap between `{}` ending at 0x{:08x} and `{}` starting at 0x{:08x}",
+                start,
+                end,
+                region_name(last),
+                range.range.end,
+                region_name(next.regions[0]),
+                next.range.start,
+            ),
+            None => bail!(
+                "0x{:08x}..0x{:08x} extends past the end of RAM: `{}` ends at 0x{:08x}",
+                start,
+                end,
+                region_name(last),
+                range.range.end,
+            ),
+        }
+    }
+
+    Ok(range
+        .regions
+        .iter()
+        .copied()
+        .filter(|r| r.range.start < end && start < r.range.end)
+        .collect())
+}
+
+/// Check that the address range `start..end` is in RAM.
+pub fn ensure_in_ram(target: &Target, start: u64, end: u64) -> Result<()> {
+    match ram_placement(target, start, end) {
+        Ok(regions) => {
+            let names: Vec<_> = regions.iter().map(|r| region_name(r)).collect();
+            log::debug!(
+                "0x{:08x}..0x{:08x} is in `{}`",
+                start,
+                end,
+                names.join("`, `")
+            );
+            Ok(())
+        }
+        Err(e) => {
+            log::warn!("segment at 0x{:08x} is not in RAM", start);
+            Err(e.wrap_err("image contains non-RAM data"))
+        }
+    }
+}
@@ -11,3 +11,42 @@ pub(crate) const VTOR: u64 = 0xE000ED08;
 
 /// Assembly for BKPT #0 on Thumb v7 (16-bit).
 pub(crate) const BKPT_ASM: &[u8; 2] = &[0x00, 0xbe];
+
+/// Configurable Fault Status Register, containing MMFSR, BFSR and UFSR.
+pub(crate) const CFSR: u64 = 0xE000ED28;
+
+/// HardFault Status Register.
+pub(crate) const HFSR: u64 = 0xE000ED2C;
+
+/// MemManage Fault Address Register.
+pub(crate) const MMFAR: u64 = 0xE000ED34;
+
+/// BusFault Address Register.
+pub(crate) const BFAR: u64 = 0xE000ED38;
+
+/// Secure Fault Status Register, on Armv8-M with the Security Extension.
+pub(crate) const SFSR: u64 = 0xE000EDE4;
+
+/// Secure Fault Address Register, on Armv8-M with the Security Extension.
+pub(crate) const SFAR: u64 = 0xE000EDE8;
+
+/// Debug Exception and Monitor Control Register, containing the vector catch bits.
+pub(crate) const DEMCR: u64 = 0xE000EDFC;
+
+/// Application Interrupt and Reset Control Register.
+pub(crate) const AIRCR: u64 = 0xE000ED0C;
+
+/// Interrupt Control and State Register, containing the SysTick and PendSV pending bits.
+pub(crate) const ICSR: u64 = 0xE000ED04;
+
+/// SysTick Control and Status Register.
+pub(crate) const SYST_CSR: u64 = 0xE000E010;
+
+/// Interrupt Controller Type Register, with the number of implemented interrupts.
+pub(crate) const ICTR: u64 = 0xE000E004;
+
+/// NVIC Interrupt Clear-Enable Registers.
+pub(crate) const NVIC_ICER: u64 = 0xE000E180;
+
+/// NVIC Interrupt Clear-Pending Registers.
+pub(crate) const NVIC_ICPR: u64 = 0xE000E280;
@@ -0,0 +1,254 @@
+use super::arm;
+use crate::backtrace::DebugInfo;
+use crate::elf::exception_name;
+use eyre::{eyre, Result};
+use probe_rs::{Core, CoreType, MemoryInterface as _};
+use std::fmt;
+
+/// A fault or reset that halts the core with the debug vector catch, instead of running the
+/// program's handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum VectorCatch {
+    /// HardFault
+    HardFault,
+    /// BusFault
+    BusFault,
+    /// MemManage fault
+    MemManage,
+    /// UsageFault from a checking error, e.g. an unaligned access or divide by zero
+    Check,
+    /// UsageFault from a state error, e.g. an undefined instruction
+    State,
+    /// UsageFault from a coprocessor access
+    NoCoprocessor,
+    /// Core reset
+    CoreReset,
+}
+
+impl VectorCatch {
+    /// The DEMCR bits of all vector catches.
+    pub(super) const ALL_BITS: u32 = 0x7f1;
+
+    /// The DEMCR bit of the vector catch.
+    pub(super) fn demcr_bit(self) -> u32 {
+        match self {
+            Self::CoreReset => 1 << 0,
+            Self::MemManage => 1 << 4,
+            Self::NoCoprocessor => 1 << 5,
+            Self::Check => 1 << 6,
+            Self::State => 1 << 7,
+            Self::BusFault => 1 << 8,
+            Self::HardFault => 1 << 10,
+        }
+    }
+}
+
+/// The CFSR bits, and the faults they report.
+const CFSR_BITS: &[(u32, &str)] = &[
+    (0, "instruction access violation"),
+    (1, "data access violation"),
+    (3, "MemManage fault on unstacking for an exception return"),
+    (4, "MemManage fault on stacking for exception entry"),
+    (5, "MemManage fault during lazy FP state preservation"),
+    (8, "instruction bus error"),
+    (9, "precise data bus error"),
+    (10, "imprecise data bus error"),
+    (11, "bus fault on unstacking for an exception return"),
+    (12, "bus fault on stacking for exception entry"),
+    (13, "bus fault during lazy FP state preservation"),
+    (16, "undefined instruction"),
+    (17, "invalid state, e.g. a branch without the Thumb bit"),
+    (18, "invalid EXC_RETURN on exception return"),
+    (19, "coprocessor access, e.g. with the FPU disabled"),
+    (20, "stack overflow"),
+    (24, "unaligned access"),
+    (25, "divide by zero"),
+];
+
+/// The HFSR bits, and the faults they report.
+const HFSR_BITS: &[(u32, &str)] = &[
+    (1, "bus fault on a vector table read"),
+    (30, "escalated from a disabled or lower priority fault"),
+];
+
+/// The SFSR bits, and the faults they report.
+const SFSR_BITS: &[(u32, &str)] = &[
+    (0, "invalid Secure state entry point"),
+    (1, "invalid integrity signature on exception return"),
+    (2, "invalid exception return"),
+    (3, "attribution unit violation"),
+    (4, "invalid transition from Secure to Non-secure state"),
+    (5, "Secure fault during lazy FP state preservation"),
+    (7, "error during lazy FP state activation"),
+];
+
+/// MMFAR holds the address of a data access violation.
+const MMARVALID: u32 = 1 << 7;
+/// BFAR holds the address of a precise data bus error.
+const BFARVALID: u32 = 1 << 15;
+/// SFAR holds the address of an attribution unit violation.
+const SFARVALID: u32 = 1 << 6;
+
+/// The registers stacked on exception entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+/// Why the program hit the HardFault handler, or a fault caught with the vector catch.
+///
+/// This is decoded from the fault status registers and the exception frame stacked when the fault
+/// was taken.
+#[derive(Debug, Clone)]
+pub struct HardFault {
+    /// The exception number, e.g. 3 for HardFault or 5 for BusFault.
+    pub exception: usize,
+    pub frame: ExceptionFrame,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    /// `None` on cores without the Security Extension.
+    pub sfsr: Option<u32>,
+    pub sfar: u32,
+    /// The function containing the stacked PC, if known.
+    pub function: Option<String>,
+}
+
+impl HardFault {
+    /// Read the fault status registers and the exception frame, with the core halted at the start
+    /// of the handler of `exception`.
+    pub fn read(core: &mut Core, exception: usize, debug_info: Option<&DebugInfo>) -> Result<Self> {
+        // Bit 2 of EXC_RETURN selects the process stack.
+        let exc_return: u32 = core.read_core_reg(core.return_address())?;
+        let sp: u32 = if exc_return & (1 << 2) == 0 {
+            core.read_core_reg(core.stack_pointer())?
+        } else {
+            let psp = core
+                .registers()
+                .psp()
+                .ok_or_else(|| eyre!("core has no process stack pointer"))?;
+            core.read_core_reg(psp)?
+        };
+        let mut stacked = [0; 8];
+        core.read_32(sp.into(), &mut stacked)?;
+        let [r0, r1, r2, r3, r12, lr, pc, xpsr] = stacked;
+        let frame = ExceptionFrame {
+            r0,
+            r1,
+            r2,
+            r3,
+            r12,
+            lr,
+            pc,
+            xpsr,
+        };
+
+        // Armv6-M doesn't have fault status registers.
+        let (cfsr, hfsr, mmfar, bfar) = match core.core_type() {
+            CoreType::Armv6m => (0, 0, 0, 0),
+            _ => (
+                core.read_word_32(arm::CFSR)?,
+                core.read_word_32(arm::HFSR)?,
+                core.read_word_32(arm::MMFAR)?,
+                core.read_word_32(arm::BFAR)?,
+            ),
+        };
+        let (sfsr, sfar) = match core.core_type() {
+            CoreType::Armv8m => (
+                core.read_word_32(arm::SFSR).ok(),
+                core.read_word_32(arm::SFAR).unwrap_or(0),
+            ),
+            _ => (None, 0),
+        };
+
+        Ok(Self {
+            exception,
+            frame,
+            cfsr,
+            hfsr,
+            mmfar,
+            bfar,
+            sfsr,
+            sfar,
+            function: debug_info.and_then(|debug_info| debug_info.function(pc)),
+        })
+    }
+
+    /// The faults reported by the status registers, e.g. `precise data bus error at 0x40021000`.
+    pub fn causes(&self) -> Vec<String> {
+        let mut causes = Vec::new();
+        for &(bit, cause) in CFSR_BITS {
+            if self.cfsr & (1 << bit) == 0 {
+                continue;
+            }
+            let address = match bit {
+                1 if self.cfsr & MMARVALID != 0 => Some(self.mmfar),
+                9 if self.cfsr & BFARVALID != 0 => Some(self.bfar),
+                _ => None,
+            };
+            causes.push(with_address(cause, address));
+        }
+        for &(bit, cause) in HFSR_BITS {
+            if self.hfsr & (1 << bit) != 0 {
+                causes.push(cause.to_owned());
+            }
+        }
+        let sfsr = self.sfsr.unwrap_or(0);
+        for &(bit, cause) in SFSR_BITS {
+            if sfsr & (1 << bit) == 0 {
+                continue;
+            }
+            let address = (bit == 3 && sfsr & SFARVALID != 0).then_some(self.sfar);
+            causes.push(with_address(cause, address));
+        }
+        causes
+    }
+}
+
+fn with_address(cause: &str, address: Option<u32>) -> String {
+    match address {
+        Some(address) => format!("{} at 0x{:08x}", cause, address),
+        None => cause.to_owned(),
+    }
+}
+
+impl fmt::Display for HardFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let causes = self.causes();
+        write!(f, "{}", exception_name(self.exception))?;
+        if !causes.is_empty() {
+            write!(f, ": {}", causes.join(", "))?;
+        }
+        write!(f, ", PC 0x{:08x}", self.frame.pc)?;
+        if let Some(function) = &self.function {
+            write!(f, " in {}", function)?;
+        }
+
+        let frame = &self.frame;
+        write!(
+            f,
+            "\n  r0  0x{:08x}  r1 0x{:08x}  r2 0x{:08x}  r3   0x{:08x}",
+            frame.r0, frame.r1, frame.r2, frame.r3
+        )?;
+        write!(
+            f,
+            "\n  r12 0x{:08x}  lr 0x{:08x}  pc 0x{:08x}  xPSR 0x{:08x}",
+            frame.r12, frame.lr, frame.pc, frame.xpsr
+        )?;
+        write!(f, "\n  CFSR 0x{:08x}  HFSR 0x{:08x}", self.cfsr, self.hfsr)?;
+        if let Some(sfsr) = self.sfsr {
+            write!(f, "  SFSR 0x{:08x}", sfsr)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for HardFault {}
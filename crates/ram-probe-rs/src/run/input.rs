@@ -0,0 +1,128 @@
+use super::ChannelSelector;
+use eyre::{Context as _, Result};
+use probe_rs::rtt::DownChannel;
+use probe_rs::Core;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+/// How input is split before it's written to a down channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputMode {
+    /// Complete lines, including the newline
+    Line,
+    /// Bytes as soon as they're read
+    Raw,
+}
+
+/// Where input for a down channel is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Stdin,
+    /// A file, which can also be a named pipe.
+    File(PathBuf),
+}
+
+impl FromStr for Source {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "-" => Self::Stdin,
+            path => Self::File(path.into()),
+        })
+    }
+}
+
+/// The source and mode of the input forwarded to a down channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub channel: ChannelSelector,
+    pub source: Source,
+    pub mode: InputMode,
+}
+
+/// Forwards input read on a separate thread to a down channel.
+pub(super) struct Forwarder {
+    channel: DownChannel,
+    receiver: Receiver<Vec<u8>>,
+    /// Input the down channel didn't have space for yet.
+    pending: Vec<u8>,
+    done: bool,
+}
+
+impl Forwarder {
+    pub fn new(channel: DownChannel, input: &Input) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let source = input.source.clone();
+        let mode = input.mode;
+        thread::Builder::new()
+            .name("rtt-input".to_owned())
+            .spawn(move || {
+                if let Err(e) = read_input(&source, mode, &sender) {
+                    log::error!("failed to read input: {:?}", e);
+                }
+            })
+            .wrap_err("failed to start input thread")?;
+
+        Ok(Self {
+            channel,
+            receiver,
+            pending: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// Write as much of the input read so far as the down channel has space for.
+    pub fn poll(&mut self, core: &mut Core) -> Result<()> {
+        while !self.done {
+            match self.receiver.try_recv() {
+                Ok(data) => self.pending.extend_from_slice(&data),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    log::debug!("end of input for down channel {}", self.channel.number());
+                    self.done = true;
+                }
+            }
+        }
+
+        if !self.pending.is_empty() {
+            let n = self.channel.write(core, &self.pending)?;
+            self.pending.drain(..n);
+        }
+        Ok(())
+    }
+}
+
+/// Read `source` until the end of the input, or until the [`Forwarder`] is dropped.
+fn read_input(source: &Source, mode: InputMode, sender: &Sender<Vec<u8>>) -> Result<()> {
+    let reader: Box<dyn Read> = match source {
+        Source::Stdin => Box::new(io::stdin()),
+        // opening a named pipe blocks until it's opened for writing
+        Source::File(path) => Box::new(
+            File::open(path).wrap_err_with(|| format!("failed to open `{}`", path.display()))?,
+        ),
+    };
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let data = match mode {
+            InputMode::Line => {
+                let mut line = Vec::new();
+                reader.read_until(b'\n', &mut line)?;
+                line
+            }
+            InputMode::Raw => {
+                let data = reader.fill_buf()?.to_vec();
+                reader.consume(data.len());
+                data
+            }
+        };
+        if data.is_empty() || sender.send(data).is_err() {
+            return Ok(());
+        }
+    }
+}
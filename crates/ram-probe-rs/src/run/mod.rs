@@ -1,76 +1,363 @@
 mod arm;
-#[cfg(feature = "defmt")]
-mod defmt;
-mod rtt;
+mod fault;
+mod input;
+mod reset;
+mod route;
+mod runner;
+mod semihosting;
+mod sink;
+mod stats;
 
+use crate::backtrace::{self, DebugInfo};
 use crate::elf::{Segments, VectorTable};
-#[cfg(feature = "defmt")]
-pub use defmt::{DefmtOpts, DefmtRunner};
 use eyre::{bail, eyre, Result};
+pub use fault::{ExceptionFrame, HardFault, VectorCatch};
+pub use input::{Input, InputMode, Source};
 use probe_rs::rtt::{Rtt, ScanRegion};
-use probe_rs::{MemoryInterface as _, Session};
-use std::time::Duration;
+use probe_rs::{Core, CoreStatus, HaltReason, MemoryInterface as _, Session};
+pub use reset::ResetStrategy;
+pub use route::{ChannelSelector, Decoding, Destination, Route, Routing};
+pub use runner::{CoreImage, Exit, RunOpts, Runner, StopReason, Stopped};
+pub use sink::{Output, RouteSink, Sink};
 
-pub fn init_cpu(
-    session: &mut Session,
-    segments: &Segments,
+/// The size of the chunks compared with the RAM contents when skipping unchanged data.
+const CHUNK_SIZE: usize = 1024;
+
+/// The number of bytes compared at the start of each exception handler when attaching.
+const SPOT_CHECK_SIZE: usize = 16;
+
+/// The RAM contents that [`init_cpu`] writes to the target.
+///
+/// This is the image with the hard fault handler patched to trigger a break point, unless faults
+/// are caught with the vector catch.
+pub fn ram_image<'data>(
+    segments: &Segments<'data>,
     vector_table: &VectorTable,
-    timeout: Duration,
-) -> Result<()> {
+    vector_catch: &[VectorCatch],
+) -> Result<Segments<'data>> {
+    let mut image = segments.clone();
+    if !vector_catch.is_empty() {
+        return Ok(image);
+    }
+    image.patch(
+        arm::thumb_v7_align!(vector_table.hard_fault).into(),
+        arm::BKPT_ASM,
+    )?;
+    Ok(image)
+}
+
+/// Stop the cores as `opts.reset` selects, write the image of each core to RAM, and start them.
+///
+/// The other cores with an image are halted without a reset after the main core is stopped, as
+/// stopping the main core may reset the whole system.
+pub fn init_cpu(session: &mut Session, opts: &RunOpts) -> Result<()> {
+    let images = opts.images();
+    check_images(session, &images)?;
+
     // Validate the main core supports RTT.
     if session.core(0)?.available_breakpoint_units()? == 0 {
         bail!("RTT not supported on device without HW breakpoints");
     }
 
-    // Reset ALL cores other than the main one.
-    for (i, _) in session.list_cores() {
-        if i != 0 {
-            log::debug!("resetting core `{}`", i);
-            session.core(i)?.reset()?;
+    // Reset ALL cores other than the ones with an image.
+    if opts.reset_other_cores {
+        for (i, _) in session.list_cores() {
+            if !images.iter().any(|image| image.core == i) {
+                log::debug!("resetting core `{}`", i);
+                session.core(i)?.reset()?;
+            }
         }
     }
 
-    // Reset and halt the main core.
-    let mut core = session.core(0)?;
-    log::debug!("resetting and halting core 0");
-    core.reset_and_halt(timeout)?;
+    // Stop the main core, then the other cores with an image.
+    reset::stop_core(session, opts.reset, opts.timeout)?;
+    for image in images.iter().skip(1) {
+        reset::halt_core(session, image.core, opts.timeout)?;
+    }
+
+    for image in images.iter() {
+        load_core(session, image, opts)?;
+    }
 
-    // Write RAM code.
-    log::info!("writing ram");
-    for (address, segment) in segments.iter() {
-        core.write_8(*address, segment)?;
+    for image in images.iter() {
+        log::debug!("restarting core {}", image.core);
+        session.core(image.core)?.run()?;
+    }
+
+    Ok(())
+}
+
+/// Write the image of a halted core to RAM, and point the core at it.
+fn load_core(session: &mut Session, image: &CoreImage, opts: &RunOpts) -> Result<()> {
+    let mut core = session.core(image.core)?;
+
+    // Write RAM code, including the patched hard fault handler unless faults are caught with the
+    // vector catch.
+    let segments = ram_image(image.segments, image.vector_table, &opts.vector_catch)?;
+    log::info!("writing ram of core {}", image.core);
+    let written = write_image(&mut core, &segments, opts.skip_unchanged)?;
+    log::info!(
+        "wrote ram ({} of {} bytes transferred)",
+        written,
+        segments
+            .iter()
+            .map(|(_, segment)| segment.len())
+            .sum::<usize>()
+    );
+    if opts.verify {
+        verify_image(&mut core, &segments)?;
+        log::debug!("verified ram");
     }
-    log::info!("wrote ram");
 
     // Init CPU to RAM code.
-    log::debug!("initializing CPU");
+    log::debug!("initializing core {}", image.core);
     let pc = core.program_counter().id();
     let sp = core.stack_pointer().id();
 
     // Reset CPU to run RAM code.
+    let vector_table = image.vector_table;
     core.write_core_reg(pc, vector_table.reset)?;
     core.write_core_reg(sp, vector_table.initial_sp)?;
     // Write VTOR location for RAM vector table.
     core.write_word_32(arm::VTOR, vector_table.address)?;
 
-    // Patch the hard fault to trigger a break point.
-    core.write_8(
-        arm::thumb_v7_align!(vector_table.hard_fault) as _,
-        arm::BKPT_ASM,
-    )?;
+    enable_vector_catch(&mut core, &opts.vector_catch)
+}
+
+/// Check that each image is for a different core of the target, and that the images don't
+/// overlap.
+fn check_images(session: &Session, images: &[CoreImage]) -> Result<()> {
+    let cores = session.list_cores();
+    for (i, image) in images.iter().enumerate() {
+        if !cores.iter().any(|&(core, _)| core == image.core) {
+            bail!(
+                "core {} not found, the target has {} cores",
+                image.core,
+                cores.len()
+            );
+        }
+        for other in images[..i].iter() {
+            if other.core == image.core {
+                bail!("more than one image for core {}", image.core);
+            }
+            for (a, a_data) in image.segments.iter() {
+                for (b, b_data) in other.segments.iter() {
+                    let (a_end, b_end) = (a + a_data.len() as u64, b + b_data.len() as u64);
+                    if a < &b_end && b < &a_end {
+                        bail!(
+                            "the image of core {} (0x{:08x}..0x{:08x}) overlaps the image of core {} (0x{:08x}..0x{:08x})",
+                            image.core,
+                            a,
+                            a_end,
+                            other.core,
+                            b,
+                            b_end
+                        );
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that the programs running on the target are the images of `opts`, without halting or
+/// resetting the cores.
+///
+/// The RAM contents change while a program runs, so only VTOR, the vector table and the start of
+/// each exception handler are compared.
+pub fn attach_cpu(session: &mut Session, opts: &RunOpts) -> Result<()> {
+    let images = opts.images();
+    check_images(session, &images)?;
+    for image in images.iter() {
+        attach_core(session, image, &opts.vector_catch)?;
+    }
+    Ok(())
+}
+
+fn attach_core(
+    session: &mut Session,
+    image: &CoreImage,
+    vector_catch: &[VectorCatch],
+) -> Result<()> {
+    let vector_table = image.vector_table;
+    let segments = ram_image(image.segments, vector_table, vector_catch)?;
+    let mut core = session.core(image.core)?;
+
+    let vtor = core.read_word_32(arm::VTOR)?;
+    if vtor != vector_table.address {
+        bail!(
+            "VTOR of core {} is 0x{:08x}, but the vector table is at 0x{:08x}; is the program running?",
+            image.core,
+            vtor,
+            vector_table.address
+        );
+    }
+
+    let mut checks = vec![(vector_table.address, vector_table.size() as usize)];
+    for &entry in vector_table.entries.iter().skip(1) {
+        let address = arm::thumb_v7_align!(entry);
+        if entry & 1 != 0 && !checks.iter().any(|&(a, _)| a == address) {
+            checks.push((address, SPOT_CHECK_SIZE));
+        }
+    }
+    for (address, len) in checks {
+        // handlers near the end of a segment are compared up to the end
+        let Some(expected) = (1..=len)
+            .rev()
+            .find_map(|len| segments.read(address.into(), len))
+        else {
+            continue;
+        };
+        let mut actual = vec![0; expected.len()];
+        core.read_8(address.into(), &mut actual)?;
+        if let Some(offset) = expected.iter().zip(&actual).position(|(a, b)| a != b) {
+            let end = expected.len().min(offset + 8);
+            bail!(
+                "the program running on core {} doesn't match the image at 0x{:08x}: expected {}, read {}",
+                image.core,
+                address as usize + offset,
+                hex_bytes(&expected[offset..end]),
+                hex_bytes(&actual[offset..end])
+            );
+        }
+    }
+    log::debug!("attached to the program running on core {}", image.core);
 
-    log::debug!("restarting CPU");
-    core.run()?;
+    enable_vector_catch(&mut core, vector_catch)
+}
 
+fn enable_vector_catch(core: &mut Core, vector_catch: &[VectorCatch]) -> Result<()> {
+    if vector_catch.is_empty() {
+        return Ok(());
+    }
+    let bits = vector_catch
+        .iter()
+        .fold(0, |bits, catch| bits | catch.demcr_bit());
+    let demcr = core.read_word_32(arm::DEMCR)? & !VectorCatch::ALL_BITS | bits;
+    log::debug!(
+        "enabling vector catch {:?}, DEMCR 0x{:08x}",
+        vector_catch,
+        demcr
+    );
+    core.write_word_32(arm::DEMCR, demcr)?;
     Ok(())
 }
 
-pub fn setup_rtt(session: &mut Session, rtt_addr: u32, retries: usize) -> Result<Rtt> {
+/// Write the image to RAM, and return the number of bytes written.
+///
+/// If `skip_unchanged` is set, the RAM contents are read first, and only the chunks that differ
+/// from the image are written. This is faster when re-running the same program over a slow probe.
+pub fn write_image(core: &mut Core, image: &Segments, skip_unchanged: bool) -> Result<usize> {
+    let mut written = 0;
+    for (address, segment) in image.iter() {
+        if !skip_unchanged {
+            core.write_8(*address, segment)?;
+            written += segment.len();
+            continue;
+        }
+
+        let mut current = vec![0; CHUNK_SIZE];
+        for (index, chunk) in segment.chunks(CHUNK_SIZE).enumerate() {
+            let chunk_address = *address + (index * CHUNK_SIZE) as u64;
+            let current = &mut current[..chunk.len()];
+            core.read_8(chunk_address, current)?;
+            if current != chunk {
+                log::trace!(
+                    "chunk at 0x{:08x} ({} bytes) changed",
+                    chunk_address,
+                    chunk.len()
+                );
+                core.write_8(chunk_address, chunk)?;
+                written += chunk.len();
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// Read back the RAM contents and check they match the image.
+pub fn verify_image(core: &mut Core, image: &Segments) -> Result<()> {
+    for (address, segment) in image.iter() {
+        let mut actual = vec![0; segment.len()];
+        core.read_8(*address, &mut actual)?;
+        if let Some(offset) = segment.iter().zip(&actual).position(|(a, b)| a != b) {
+            let end = segment.len().min(offset + 8);
+            bail!(
+                "RAM verification failed at 0x{:08x}: expected {}, read {} (segment at 0x{:08x}, {} bytes)",
+                *address + offset as u64,
+                hex_bytes(&segment[offset..end]),
+                hex_bytes(&actual[offset..end]),
+                address,
+                segment.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Check how the program exited after the core halted.
+///
+/// If the program hit the hard fault handler, or the vector catch halted the core on a fault, a
+/// backtrace is printed and the decoded [`HardFault`] is returned as the error.
+///
+/// The program exits with a semihosting `SYS_EXIT`, or with any other `BKPT` instruction. The exit
+/// status of a `BKPT` is read from `exit_code_addr` if given, and is otherwise its immediate value.
+fn halted(
+    core: &mut Core,
+    hard_fault: u32,
+    exit_code_addr: Option<u32>,
+    debug_info: Option<&DebugInfo>,
+) -> Result<Exit> {
+    let pc: u32 = core.read_core_reg(core.program_counter())?;
+    let caught = matches!(core.status()?, CoreStatus::Halted(HaltReason::Exception));
+    if caught || pc == hard_fault {
+        let psr = core
+            .registers()
+            .psr()
+            .ok_or_else(|| eyre!("core has no xPSR"))?;
+        let xpsr: u32 = core.read_core_reg(psr)?;
+        // IPSR is the number of the exception being handled.
+        let exception = (xpsr & 0x1ff) as usize;
+        if exception == 0 {
+            bail!("program reset the core, caught at 0x{:08x}", pc);
+        }
+        let fault = HardFault::read(core, exception, debug_info)?;
+        if let Some(debug_info) = debug_info {
+            let backtrace = backtrace::unwind(core, debug_info)?;
+            log::error!("{}", backtrace);
+        }
+        return Err(fault.into());
+    }
+
+    let mut instruction = [0; 2];
+    core.read_8(pc.into(), &mut instruction)?;
+    let [immediate, 0xbe] = instruction else {
+        bail!(
+            "core halted at 0x{:08x}, which is not a BKPT instruction",
+            pc
+        );
+    };
+    let status = match (immediate, exit_code_addr) {
+        (semihosting::BKPT_IMM, _) => semihosting::exit_status(core)?,
+        (_, Some(addr)) => core.read_word_32(addr.into())? as i32,
+        (immediate, None) => immediate.into(),
+    };
+    log::debug!("program exited at 0x{:08x} with status {}", pc, status);
+    Ok(Exit { status })
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<_> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    bytes.join(" ")
+}
+
+pub fn setup_rtt(session: &mut Session, core: usize, rtt_addr: u32, retries: usize) -> Result<Rtt> {
     let mut rtt_res: Result<Rtt, probe_rs::rtt::Error> =
         Err(probe_rs::rtt::Error::ControlBlockNotFound);
 
     let memory_map = session.target().memory_map.clone();
-    let mut core = session.core(0).unwrap();
+    let mut core = session.core(core)?;
 
     for try_index in 0..=retries {
         rtt_res = Rtt::attach_region(&mut core, &memory_map, &ScanRegion::Exact(rtt_addr));
@@ -0,0 +1,153 @@
+use super::{arm, VectorCatch};
+use eyre::{bail, eyre, Result};
+use probe_rs::architecture::arm::{ap::AccessPortError, ArmError};
+use probe_rs::{Core, CoreType, MemoryInterface as _, Session};
+use std::time::{Duration, Instant};
+
+/// Writing `AIRCR` requires this key in bits [31:16].
+const AIRCR_VECTKEY: u32 = 0x05fa << 16;
+const AIRCR_SYSRESETREQ: u32 = 1 << 2;
+
+const ICSR_PENDSTCLR: u32 = 1 << 25;
+const ICSR_PENDSVCLR: u32 = 1 << 27;
+
+/// How the main core is stopped before the program is written to RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ResetStrategy {
+    /// Reset with the chip's reset sequence, and halt before the first instruction
+    #[default]
+    Sequence,
+    /// Reset the system with `SYSRESETREQ`, and halt before the first instruction
+    Sysresetreq,
+    /// Reset with the probe's hardware reset pin while connecting, which halts the cores before
+    /// the first instruction
+    Hardware,
+    /// Halt without a reset, keeping the peripheral state, e.g. set up by a ROM bootloader
+    Halt,
+}
+
+/// Stop core 0 with `strategy`, and leave it halted.
+pub(super) fn stop_core(
+    session: &mut Session,
+    strategy: ResetStrategy,
+    timeout: Duration,
+) -> Result<()> {
+    match strategy {
+        ResetStrategy::Sequence => {
+            log::debug!("resetting and halting core 0");
+            session.core(0)?.reset_and_halt(timeout)?;
+        }
+        ResetStrategy::Sysresetreq => {
+            let mut core = session.core(0)?;
+            log::debug!("resetting core 0 with SYSRESETREQ");
+            set_reset_catch(&mut core, true)?;
+            match core.write_word_32(arm::AIRCR, AIRCR_VECTKEY | AIRCR_SYSRESETREQ) {
+                Err(e) if !is_reset_error(&e) => return Err(e.into()),
+                _ => {}
+            }
+            wait_for_reset_halt(&mut core, timeout)?;
+            set_reset_catch(&mut core, false)?;
+        }
+        ResetStrategy::Hardware => {
+            // the cores are halted after the reset pin is released when connecting under reset
+            if !session.core(0)?.core_halted()? {
+                bail!("the hardware reset strategy requires connecting under reset");
+            }
+            log::debug!("core 0 halted after the hardware reset");
+        }
+        ResetStrategy::Halt => halt_core(session, 0, timeout)?,
+    }
+    Ok(())
+}
+
+/// Halt a core without a reset, and clear the state left by the code it was running.
+pub(super) fn halt_core(session: &mut Session, index: usize, timeout: Duration) -> Result<()> {
+    log::debug!("halting core {}", index);
+    let mut core = session.core(index)?;
+    core.halt(timeout)?;
+    clear_core_state(&mut core)
+}
+
+/// Wait for the core to halt on the reset vector catch, through the errors of the reset window.
+fn wait_for_reset_halt(core: &mut Core, timeout: Duration) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        match core.core_halted() {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) if is_reset_error(&e) => log::debug!("ignoring error during reset: {}", e),
+            Err(e) => return Err(e.into()),
+        }
+        if start.elapsed() > timeout {
+            bail!("core {} didn't halt after SYSRESETREQ", core.id());
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// Whether an access port error may be caused by the target resetting, which some probes report
+/// for accesses while the reset is in progress.
+fn is_reset_error(error: &probe_rs::Error) -> bool {
+    matches!(
+        error,
+        probe_rs::Error::Arm(ArmError::AccessPort {
+            source: AccessPortError::RegisterRead { .. } | AccessPortError::RegisterWrite { .. },
+            ..
+        })
+    )
+}
+
+/// Set or clear the core reset vector catch, which halts the core after a reset.
+fn set_reset_catch(core: &mut Core, enabled: bool) -> Result<()> {
+    let bit = VectorCatch::CoreReset.demcr_bit();
+    let demcr = core.read_word_32(arm::DEMCR)?;
+    let demcr = if enabled { demcr | bit } else { demcr & !bit };
+    core.write_word_32(arm::DEMCR, demcr)?;
+    Ok(())
+}
+
+/// Put a core halted without a reset into its reset state, as far as the program can tell.
+///
+/// Fails if the core was halted in an exception handler, as the exception would stay active.
+///
+/// The interrupts and SysTick left enabled by the previous program are disabled, their pending
+/// bits are cleared, and PRIMASK, FAULTMASK, BASEPRI and CONTROL are zeroed, so the program
+/// starts privileged on the main stack.
+fn clear_core_state(core: &mut Core) -> Result<()> {
+    let psr = core
+        .registers()
+        .psr()
+        .ok_or_else(|| eyre!("core has no xPSR"))?;
+    let xpsr: u32 = core.read_core_reg(psr)?;
+    // Only a reset leaves the handler mode of an active exception.
+    let exception = xpsr & 0x1ff;
+    if exception != 0 {
+        bail!(
+            "core {} was halted in exception {}, which only a reset ends, use `--reset sequence` or `--reset sysresetreq`",
+            core.id(),
+            exception
+        );
+    }
+
+    // Armv6-M has up to 32 interrupts, and doesn't have ICTR.
+    let words = match core.core_type() {
+        CoreType::Armv6m => 1,
+        _ => (core.read_word_32(arm::ICTR)? & 0xf) + 1,
+    };
+    log::debug!("disabling {} interrupts", words * 32);
+    for i in 0..u64::from(words) {
+        core.write_word_32(arm::NVIC_ICER + 4 * i, 0xffff_ffff)?;
+        core.write_word_32(arm::NVIC_ICPR + 4 * i, 0xffff_ffff)?;
+    }
+    core.write_word_32(arm::SYST_CSR, 0)?;
+    core.write_word_32(arm::ICSR, ICSR_PENDSTCLR | ICSR_PENDSVCLR)?;
+
+    // CONTROL, FAULTMASK, BASEPRI and PRIMASK are packed into one register.
+    let extra = core
+        .registers()
+        .other_by_name("EXTRA")
+        .ok_or_else(|| eyre!("core has no CONTROL register"))?
+        .id();
+    core.write_core_reg(extra, 0u32)?;
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// An RTT channel, by number or by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelSelector {
+    Number(usize),
+    Name(String),
+}
+
+impl ChannelSelector {
+    pub fn matches(&self, number: usize, name: Option<&str>) -> bool {
+        match self {
+            Self::Number(n) => *n == number,
+            Self::Name(n) => Some(n.as_str()) == name,
+        }
+    }
+}
+
+impl FromStr for ChannelSelector {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(number) => Self::Number(number),
+            Err(_) => Self::Name(s.to_owned()),
+        })
+    }
+}
+
+impl fmt::Display for ChannelSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(number) => write!(f, "{}", number),
+            Self::Name(name) => write!(f, "`{}`", name),
+        }
+    }
+}
+
+/// How the data of an up channel is decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Decoding {
+    /// `defmt` frames
+    #[cfg(feature = "defmt")]
+    Defmt,
+    /// UTF-8 text, with invalid sequences replaced
+    Text,
+    /// Raw bytes, unchanged
+    Raw,
+}
+
+impl FromStr for Decoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as clap::ValueEnum>::from_str(s, true)
+            .map_err(|_| format!("unknown decoding `{}`", s))
+    }
+}
+
+/// Where the output of an up channel is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// Text and raw data is written to stdout, and `defmt` frames are logged.
+    Stdout,
+    /// A file, which is created or truncated. This can also be a named pipe.
+    File(PathBuf),
+}
+
+impl FromStr for Destination {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "-" => Self::Stdout,
+            path => Self::File(path.into()),
+        })
+    }
+}
+
+/// The decoding and destination of an up channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub channel: ChannelSelector,
+    pub decoding: Decoding,
+    pub destination: Destination,
+}
+
+/// Parses `CHANNEL=DECODING[:PATH]`, e.g. `0=defmt` or `samples=raw:samples.bin`.
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((channel, rest)) = s.split_once('=') else {
+            return Err(format!("expected `CHANNEL=DECODING[:PATH]`, got `{}`", s));
+        };
+        let (decoding, destination) = match rest.split_once(':') {
+            Some((_, "")) => return Err(format!("empty path in `{}`", s)),
+            Some((decoding, path)) => (decoding, Destination::from_str(path).unwrap()),
+            None => (rest, Destination::Stdout),
+        };
+        Ok(Self {
+            channel: ChannelSelector::from_str(channel).unwrap(),
+            decoding: decoding.parse()?,
+            destination,
+        })
+    }
+}
+
+/// The routes of all up channels.
+#[derive(Debug, Clone)]
+pub struct Routing {
+    pub routes: Vec<Route>,
+    /// The decoding of channels without a route, which are written to stdout.
+    pub default: Decoding,
+}
+
+impl Routing {
+    pub fn new(default: Decoding) -> Self {
+        Self {
+            routes: Vec::new(),
+            default,
+        }
+    }
+
+    pub fn with_route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// The first route that matches the channel.
+    pub fn route(&self, number: usize, name: Option<&str>) -> Option<&Route> {
+        self.routes
+            .iter()
+            .find(|route| route.channel.matches(number, name))
+    }
+
+    pub fn decoding(&self, number: usize, name: Option<&str>) -> Decoding {
+        self.route(number, name)
+            .map_or(self.default, |route| route.decoding)
+    }
+
+    pub fn destination(&self, number: usize, name: Option<&str>) -> &Destination {
+        self.route(number, name)
+            .map_or(&Destination::Stdout, |route| &route.destination)
+    }
+}
+
+impl Default for Routing {
+    fn default() -> Self {
+        Self::new(Decoding::Text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "defmt")]
+    #[test]
+    fn parse_route_to_stdout() {
+        let route: Route = "0=defmt".parse().unwrap();
+        assert_eq!(route.channel, ChannelSelector::Number(0));
+        assert_eq!(route.decoding, Decoding::Defmt);
+        assert_eq!(route.destination, Destination::Stdout);
+    }
+
+    #[test]
+    fn parse_route_to_file() {
+        let route: Route = "name=raw:file.bin".parse().unwrap();
+        assert_eq!(route.channel, ChannelSelector::Name("name".to_owned()));
+        assert_eq!(route.decoding, Decoding::Raw);
+        assert_eq!(route.destination, Destination::File("file.bin".into()));
+    }
+
+    #[test]
+    fn parse_route_to_explicit_stdout() {
+        let route: Route = "1=text:-".parse().unwrap();
+        assert_eq!(route.channel, ChannelSelector::Number(1));
+        assert_eq!(route.decoding, Decoding::Text);
+        assert_eq!(route.destination, Destination::Stdout);
+    }
+
+    #[test]
+    fn reject_invalid_routes() {
+        assert_eq!(
+            "0=hex".parse::<Route>().unwrap_err(),
+            "unknown decoding `hex`"
+        );
+        assert!("0"
+            .parse::<Route>()
+            .unwrap_err()
+            .contains("CHANNEL=DECODING"));
+        assert_eq!(
+            "0=raw:".parse::<Route>().unwrap_err(),
+            "empty path in `0=raw:`"
+        );
+    }
+}
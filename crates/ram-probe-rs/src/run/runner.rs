@@ -0,0 +1,550 @@
+use super::input::Forwarder;
+use super::semihosting::Semihosting;
+use super::stats::Stats;
+use super::{Decoding, Input, Output, ResetStrategy, Routing, Sink, VectorCatch};
+use crate::backtrace::DebugInfo;
+#[cfg(feature = "defmt")]
+use crate::defmt::{DefmtDecoder, DefmtInfo};
+use crate::elf::{Segments, VectorTable};
+use eyre::{bail, Context as _, Result};
+use probe_rs::rtt::UpChannel;
+use probe_rs::{Core, Session};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The shortest and longest sleep between polls while the program doesn't write any output.
+///
+/// The sleep doubles after each empty poll, so the probe isn't kept busy by an idle program.
+const MIN_IDLE_SLEEP: Duration = Duration::from_micros(500);
+const MAX_IDLE_SLEEP: Duration = Duration::from_millis(20);
+
+/// A program run on one core.
+#[derive(Clone, Copy)]
+pub struct CoreImage<'a> {
+    /// The index of the core in the target description.
+    pub core: usize,
+    pub segments: &'a Segments<'a>,
+    pub rtt_addr: u32,
+    pub vector_table: &'a VectorTable,
+    /// The address of the exit status, which is read when the program hits a `BKPT` instruction.
+    pub exit_code_addr: Option<u32>,
+    /// Required to decode channels as `defmt`.
+    #[cfg(feature = "defmt")]
+    pub defmt: Option<&'a DefmtInfo>,
+    /// Used to print a backtrace if the program faults.
+    pub debug_info: Option<&'a DebugInfo<'a>>,
+}
+
+impl<'a> CoreImage<'a> {
+    pub fn new(
+        core: usize,
+        segments: &'a Segments<'a>,
+        rtt_addr: u32,
+        vector_table: &'a VectorTable,
+    ) -> Self {
+        Self {
+            core,
+            segments,
+            rtt_addr,
+            vector_table,
+            exit_code_addr: None,
+            #[cfg(feature = "defmt")]
+            defmt: None,
+            debug_info: None,
+        }
+    }
+}
+
+pub struct RunOpts<'a> {
+    pub segments: &'a Segments<'a>,
+    pub rtt_addr: u32,
+    pub vector_table: &'a VectorTable,
+    /// The decoding of each up channel.
+    pub routing: Routing,
+    /// Decode up channel 0 as `defmt` if it has no route, on each core whose program has `defmt`
+    /// info.
+    #[cfg(feature = "defmt")]
+    pub auto_defmt: bool,
+    /// The address of the exit status, which is read when the program hits a `BKPT` instruction.
+    pub exit_code_addr: Option<u32>,
+    /// The directory that semihosting file I/O is sandboxed to, or `None` to only allow the
+    /// console.
+    pub semihosting_root: Option<PathBuf>,
+    /// Input forwarded to a down channel.
+    pub input: Option<Input>,
+    /// Required to decode channels as `defmt`.
+    #[cfg(feature = "defmt")]
+    pub defmt: Option<&'a DefmtInfo>,
+    /// Used to print a backtrace if the program faults.
+    pub debug_info: Option<&'a DebugInfo<'a>>,
+    pub timeout: Duration,
+    pub retries: usize,
+    /// Stop the program if it hasn't exited after this long.
+    pub run_timeout: Option<Duration>,
+    /// Stop the program when this is set, e.g. by a signal handler.
+    pub interrupt: Option<Arc<AtomicBool>>,
+    /// Report the throughput of each up channel, and how often its buffer was full.
+    pub stats: bool,
+    /// Read back the RAM contents after writing the image.
+    pub verify: bool,
+    /// Only write the parts of the image that differ from the RAM contents.
+    pub skip_unchanged: bool,
+    /// How the main core is stopped before the image is written.
+    pub reset: ResetStrategy,
+    /// Reset the other cores, instead of leaving them running.
+    pub reset_other_cores: bool,
+    /// Catch these faults with the debug vector catch, instead of patching the hard fault handler.
+    pub vector_catch: Vec<VectorCatch>,
+    /// Attach to the program already running on the target, instead of downloading and starting
+    /// it.
+    pub attach: bool,
+    /// Programs run on the other cores, alongside the program on the main core (core 0).
+    pub cores: Vec<CoreImage<'a>>,
+}
+
+impl<'a> RunOpts<'a> {
+    pub fn with_defaults(
+        segments: &'a Segments<'a>,
+        rtt_addr: u32,
+        vector_table: &'a VectorTable,
+    ) -> Self {
+        Self {
+            segments,
+            rtt_addr,
+            vector_table,
+            routing: Routing::default(),
+            #[cfg(feature = "defmt")]
+            auto_defmt: false,
+            exit_code_addr: None,
+            semihosting_root: None,
+            input: None,
+            #[cfg(feature = "defmt")]
+            defmt: None,
+            debug_info: None,
+            timeout: Duration::from_secs(1),
+            retries: 10,
+            run_timeout: None,
+            interrupt: None,
+            stats: false,
+            verify: true,
+            skip_unchanged: false,
+            reset: ResetStrategy::default(),
+            reset_other_cores: true,
+            vector_catch: Vec::new(),
+            attach: false,
+            cores: Vec::new(),
+        }
+    }
+
+    /// The program on the main core, followed by the programs on the other cores.
+    pub(super) fn images(&self) -> Vec<CoreImage<'a>> {
+        let main = CoreImage {
+            core: 0,
+            segments: self.segments,
+            rtt_addr: self.rtt_addr,
+            vector_table: self.vector_table,
+            exit_code_addr: self.exit_code_addr,
+            #[cfg(feature = "defmt")]
+            defmt: self.defmt,
+            debug_info: self.debug_info,
+        };
+        std::iter::once(main)
+            .chain(self.cores.iter().copied())
+            .collect()
+    }
+
+    /// The decoding of an up channel of the program on a core.
+    #[cfg_attr(not(feature = "defmt"), allow(unused_variables))]
+    fn decoding(&self, image: &CoreImage, number: usize, name: Option<&str>) -> Decoding {
+        #[cfg(feature = "defmt")]
+        if self.auto_defmt
+            && number == 0
+            && image.defmt.is_some()
+            && self.routing.route(number, name).is_none()
+        {
+            return Decoding::Defmt;
+        }
+        self.routing.decoding(number, name)
+    }
+}
+
+/// How a program exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exit {
+    /// The exit status, which is 0 on success.
+    pub status: i32,
+}
+
+impl Exit {
+    pub fn is_success(&self) -> bool {
+        self.status == 0
+    }
+}
+
+/// Why a program was stopped before it exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The run timeout elapsed.
+    Timeout(Duration),
+    /// The run was interrupted, e.g. with Ctrl-C.
+    Interrupted,
+}
+
+/// The program was stopped before it exited.
+#[derive(Debug, Clone)]
+pub struct Stopped {
+    pub reason: StopReason,
+    /// Where the core was halted.
+    pub pc: u32,
+    /// The function containing `pc`, if known.
+    pub function: Option<String>,
+}
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reason {
+            StopReason::Timeout(timeout) => write!(f, "timed out after {:?}", timeout)?,
+            StopReason::Interrupted => write!(f, "interrupted")?,
+        }
+        write!(f, ", core halted at 0x{:08x}", self.pc)?;
+        if let Some(function) = &self.function {
+            write!(f, " in {}", function)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+/// The RTT channels of the program on one core, and how far it has run.
+struct CoreRun<'opts> {
+    /// The index of the core.
+    core: usize,
+    channels: Vec<UpChannel>,
+    stats: Stats,
+    /// The `defmt` decoder of each channel decoded as `defmt`.
+    #[cfg(feature = "defmt")]
+    decoders: Vec<Option<DefmtDecoder<'opts>>>,
+    semihosting: Semihosting,
+    hard_fault: u32,
+    exit_code_addr: Option<u32>,
+    debug_info: Option<&'opts DebugInfo<'opts>>,
+    /// Whether the core was halted at the last poll.
+    was_halted: bool,
+    /// Whether the program exited. Only the programs on the other cores are polled after exiting.
+    exited: bool,
+}
+
+/// Runs a program from RAM, and passes the RTT output to a [`Sink`] until the program halts.
+///
+/// With programs on several cores, the run ends when the program on the main core exits.
+pub struct Runner<'opts> {
+    /// The main core first.
+    cores: Vec<CoreRun<'opts>>,
+    /// Large enough to read the whole buffer of any up channel.
+    read_buf: Vec<u8>,
+    /// Input forwarded to a down channel of the main core.
+    input: Option<Forwarder>,
+    timeout: Duration,
+    run_timeout: Option<Duration>,
+    interrupt: Option<Arc<AtomicBool>>,
+}
+
+impl<'opts> Runner<'opts> {
+    /// Download the programs and start them, or check they're already running if `attach` is set,
+    /// and attach RTT.
+    pub fn new(session: &mut Session, opts: &'opts RunOpts<'_>) -> Result<Self> {
+        if opts.attach {
+            super::attach_cpu(session, opts)?;
+        } else {
+            super::init_cpu(session, opts)?;
+        }
+
+        let images = opts.images();
+        let mut cores = Vec::new();
+        let mut input = None;
+        let mut found = vec![false; opts.routing.routes.len()];
+        for image in images.iter() {
+            let mut rtt = super::setup_rtt(session, image.core, image.rtt_addr, opts.retries)?;
+
+            for (route, found) in opts.routing.routes.iter().zip(found.iter_mut()) {
+                *found |= rtt
+                    .up_channels()
+                    .iter()
+                    .any(|ch| route.channel.matches(ch.number(), ch.name()));
+            }
+
+            if let (0, Some(opts_input)) = (image.core, &opts.input) {
+                let number = rtt
+                    .down_channels()
+                    .iter()
+                    .find(|ch| opts_input.channel.matches(ch.number(), ch.name()))
+                    .map(|ch| ch.number());
+                let Some(channel) = number.and_then(|number| rtt.down_channels().take(number))
+                else {
+                    bail!("RTT down channel {} not found", opts_input.channel);
+                };
+                log::debug!(
+                    "forwarding {:?} to down channel {} as {:?}",
+                    opts_input.source,
+                    channel.number(),
+                    opts_input.mode
+                );
+                input = Some(Forwarder::new(channel, opts_input)?);
+            }
+
+            let numbers: Vec<_> = rtt.up_channels().iter().map(|ch| ch.number()).collect();
+            let channels: Vec<_> = numbers
+                .into_iter()
+                .filter_map(|number| rtt.up_channels().take(number))
+                .collect();
+            for channel in channels.iter() {
+                log::debug!(
+                    "decoding core {} up channel {} as {:?}",
+                    image.core,
+                    channel.number(),
+                    opts.decoding(image, channel.number(), channel.name())
+                );
+            }
+
+            #[cfg(feature = "defmt")]
+            let decoders = channels
+                .iter()
+                .map(|channel| {
+                    let decoding = opts.decoding(image, channel.number(), channel.name());
+                    match (decoding, image.defmt) {
+                        (Decoding::Defmt, Some(defmt)) => Ok(Some(DefmtDecoder::new(defmt))),
+                        (Decoding::Defmt, None) => bail!(
+                            "core {} up channel {} is decoded as defmt, but defmt info not found",
+                            image.core,
+                            channel.number()
+                        ),
+                        _ => Ok(None),
+                    }
+                })
+                .collect::<Result<_>>()?;
+
+            let label = (images.len() > 1).then_some(image.core);
+            cores.push(CoreRun {
+                core: image.core,
+                stats: Stats::new(&channels, opts.stats, label),
+                channels,
+                #[cfg(feature = "defmt")]
+                decoders,
+                semihosting: Semihosting::new(opts.semihosting_root.clone()),
+                hard_fault: super::arm::thumb_v7_align!(image.vector_table.hard_fault),
+                exit_code_addr: image.exit_code_addr,
+                debug_info: image.debug_info,
+                was_halted: false,
+                exited: false,
+            });
+        }
+        if let Some((route, _)) = opts.routing.routes.iter().zip(found).find(|(_, f)| !f) {
+            bail!("RTT up channel {} not found", route.channel);
+        }
+
+        let buffer_size = cores
+            .iter()
+            .flat_map(|core| core.channels.iter().map(UpChannel::buffer_size))
+            .max();
+        Ok(Self {
+            cores,
+            read_buf: vec![0; buffer_size.unwrap_or(0)],
+            input,
+            timeout: opts.timeout,
+            run_timeout: opts.run_timeout,
+            interrupt: opts.interrupt.clone(),
+        })
+    }
+
+    /// Poll the RTT channels and service semihosting requests until the program exits.
+    ///
+    /// The run ends when the program on core 0 exits. If it succeeds, but a program on another
+    /// core exited with a failure before, the first such exit is returned instead.
+    ///
+    /// If the program hits the hard fault handler, the error is a [`HardFault`](super::HardFault).
+    /// If it times out or is interrupted, the cores are halted, the remaining RTT data is read, and
+    /// the error is [`Stopped`].
+    pub fn run(&mut self, session: &mut Session, sink: &mut dyn Sink) -> Result<Exit> {
+        let result = self.run_until_exit(session, sink);
+        for core in self.cores.iter() {
+            core.stats.summary();
+        }
+        result
+    }
+
+    fn run_until_exit(&mut self, session: &mut Session, sink: &mut dyn Sink) -> Result<Exit> {
+        let start = Instant::now();
+        let mut idle_sleep = Duration::ZERO;
+        // The first failed exit of the programs on the other cores.
+        let mut failed = None;
+
+        loop {
+            let mut busy = false;
+            let mut any_halted = false;
+            for run in self.cores.iter_mut() {
+                // Read the channels and the halt status with the same core handle.
+                let mut core = session.core(run.core)?;
+                busy |= run.read_channels(&mut core, &mut self.read_buf, sink)? > 0;
+                run.stats.report_if_due();
+                if run.core == 0 {
+                    if let Some(input) = &mut self.input {
+                        input.poll(&mut core)?;
+                    }
+                }
+                if run.exited {
+                    continue;
+                }
+
+                let mut is_halted = core.core_halted()?;
+                if is_halted && run.semihosting.service(&mut core)? {
+                    // the core was resumed
+                    is_halted = false;
+                    busy = true;
+                }
+                if is_halted && run.was_halted {
+                    let exit = super::halted(
+                        &mut core,
+                        run.hard_fault,
+                        run.exit_code_addr,
+                        run.debug_info,
+                    );
+                    if run.core == 0 {
+                        let exit = exit?;
+                        return Ok(if exit.is_success() {
+                            failed.unwrap_or(exit)
+                        } else {
+                            exit
+                        });
+                    }
+                    let exit =
+                        exit.wrap_err_with(|| format!("the program on core {} failed", run.core))?;
+                    if exit.is_success() {
+                        log::info!("the program on core {} exited", run.core);
+                    } else {
+                        log::error!(
+                            "the program on core {} exited with status {}",
+                            run.core,
+                            exit.status
+                        );
+                        failed = failed.or(Some(exit));
+                    }
+                    run.exited = true;
+                }
+                run.was_halted = is_halted;
+                any_halted |= is_halted;
+            }
+
+            let interrupted = self
+                .interrupt
+                .as_ref()
+                .is_some_and(|interrupt| interrupt.load(Ordering::Relaxed));
+            let reason = if interrupted {
+                Some(StopReason::Interrupted)
+            } else {
+                self.run_timeout
+                    .filter(|&timeout| start.elapsed() >= timeout)
+                    .map(StopReason::Timeout)
+            };
+            if let Some(reason) = reason {
+                return Err(self.stop(session, sink, reason)?.into());
+            }
+
+            if busy || any_halted {
+                idle_sleep = Duration::ZERO;
+            } else {
+                thread::sleep(idle_sleep);
+                idle_sleep = (idle_sleep * 2).clamp(MIN_IDLE_SLEEP, MAX_IDLE_SLEEP);
+            }
+        }
+    }
+
+    /// Halt the cores, and pass the remaining RTT data to `sink`.
+    ///
+    /// The PC reported is the main core's.
+    fn stop(
+        &mut self,
+        session: &mut Session,
+        sink: &mut dyn Sink,
+        reason: StopReason,
+    ) -> Result<Stopped> {
+        log::debug!("stopping program: {:?}", reason);
+        for run in self.cores.iter().skip(1) {
+            session.core(run.core)?.halt(self.timeout)?;
+        }
+        let pc = session.core(0)?.halt(self.timeout)?.pc as u32;
+        while self.poll(session, sink)? > 0 {}
+        Ok(Stopped {
+            reason,
+            pc,
+            function: self.cores[0]
+                .debug_info
+                .and_then(|debug_info| debug_info.function(pc)),
+        })
+    }
+
+    /// Read the RTT up channels of all cores once and pass the output to `sink`, then forward any
+    /// pending input. Returns the number of bytes read.
+    pub fn poll(&mut self, session: &mut Session, sink: &mut dyn Sink) -> Result<usize> {
+        let mut read = 0;
+        for run in self.cores.iter_mut() {
+            let mut core = session.core(run.core)?;
+            read += run.read_channels(&mut core, &mut self.read_buf, sink)?;
+            if run.core == 0 {
+                if let Some(input) = &mut self.input {
+                    input.poll(&mut core)?;
+                }
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl CoreRun<'_> {
+    fn read_channels(
+        &mut self,
+        core: &mut Core,
+        read_buf: &mut [u8],
+        sink: &mut dyn Sink,
+    ) -> Result<usize> {
+        let mut read = 0;
+
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            let n = channel.read(core, read_buf)?;
+            if n == 0 {
+                continue;
+            }
+            read += n;
+            self.stats.record(index, n);
+            let number = channel.number();
+            let name = channel.name();
+            let data = &read_buf[..n];
+
+            #[cfg(feature = "defmt")]
+            if let Some(defmt) = &mut self.decoders[index] {
+                defmt.decode(data, |frame, location| {
+                    sink.output(Output::Frame {
+                        core: self.core,
+                        channel: number,
+                        name,
+                        frame,
+                        location,
+                    })
+                })?;
+                continue;
+            }
+
+            sink.output(Output::Bytes {
+                core: self.core,
+                channel: number,
+                name,
+                data,
+            })?;
+        }
+        Ok(read)
+    }
+}
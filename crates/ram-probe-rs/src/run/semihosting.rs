@@ -0,0 +1,385 @@
+use eyre::{bail, Result};
+use probe_rs::{Core, MemoryInterface as _};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+
+/// The `BKPT` immediate value of a semihosting request on Arm-M.
+pub(super) const BKPT_IMM: u8 = 0xab;
+
+const SYS_OPEN: u32 = 0x01;
+const SYS_CLOSE: u32 = 0x02;
+const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_WRITE: u32 = 0x05;
+const SYS_READ: u32 = 0x06;
+const SYS_ISTTY: u32 = 0x09;
+const SYS_SEEK: u32 = 0x0a;
+const SYS_FLEN: u32 = 0x0c;
+const SYS_CLOCK: u32 = 0x10;
+const SYS_ERRNO: u32 = 0x13;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+
+/// The `SYS_EXIT` reason of a normal exit.
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+/// The error number of an unsupported operation.
+const ENOSYS: i32 = 38;
+
+/// The special file name of the host console.
+const CONSOLE: &str = ":tt";
+
+/// The maximum number of bytes transferred by one `SYS_READ` or `SYS_WRITE`.
+///
+/// The program sees a partial transfer, and can repeat the request for the rest.
+const MAX_TRANSFER: u32 = 0x10000;
+
+/// A file opened by the program.
+enum Handle {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+/// Services the semihosting requests of a program, with file I/O sandboxed to a directory.
+pub(super) struct Semihosting {
+    /// The directory files are opened in, or `None` to only allow the console.
+    root: Option<PathBuf>,
+    /// The open files, where handle `n` is at index `n - 1`.
+    handles: Vec<Option<Handle>>,
+    /// The error number of the last failed request.
+    errno: i32,
+    start: Instant,
+}
+
+impl Semihosting {
+    pub fn new(root: Option<PathBuf>) -> Self {
+        Self {
+            root,
+            handles: Vec::new(),
+            errno: 0,
+            start: Instant::now(),
+        }
+    }
+
+    /// Service the request if the core halted on a semihosting breakpoint, and resume the core.
+    ///
+    /// Returns `false` if the core halted for another reason, or if the program exited with
+    /// `SYS_EXIT` or `SYS_EXIT_EXTENDED`.
+    pub fn service(&mut self, core: &mut Core) -> Result<bool> {
+        let pc: u32 = core.read_core_reg(core.program_counter())?;
+        let mut instruction = [0; 2];
+        core.read_8(pc.into(), &mut instruction)?;
+        if instruction != [BKPT_IMM, 0xbe] {
+            return Ok(false);
+        }
+
+        let operation: u32 = core.read_core_reg(0)?;
+        let parameter: u32 = core.read_core_reg(1)?;
+        if matches!(operation, SYS_EXIT | SYS_EXIT_EXTENDED) {
+            return Ok(false);
+        }
+
+        let result = self.request(core, operation, parameter)?;
+        log::trace!(
+            "semihosting operation 0x{:02x} at 0x{:08x} returned {}",
+            operation,
+            pc,
+            result
+        );
+        core.write_core_reg(0, result as u32)?;
+        core.write_core_reg(core.program_counter(), pc + 2)?;
+        core.run()?;
+        Ok(true)
+    }
+
+    /// Perform a request on the host, and return the result for `r0`.
+    ///
+    /// Host I/O errors and unsupported operations are reported to the program, and probe errors
+    /// are returned.
+    fn request(&mut self, core: &mut Core, operation: u32, parameter: u32) -> Result<i32> {
+        let result = match operation {
+            SYS_OPEN => {
+                let [name, mode, len] = read_block(core, parameter)?;
+                let mut name_buf = vec![0; len.min(MAX_TRANSFER) as usize];
+                core.read_8(name.into(), &mut name_buf)?;
+                let name = String::from_utf8_lossy(&name_buf);
+                self.open(&name, mode)
+            }
+            SYS_CLOSE => {
+                let [handle] = read_block(core, parameter)?;
+                self.close(handle)
+            }
+            SYS_WRITEC => {
+                let mut c = [0];
+                core.read_8(parameter.into(), &mut c)?;
+                write_stdout(&c).map(|()| 0)
+            }
+            SYS_WRITE0 => {
+                let data = read_c_string(core, parameter)?;
+                write_stdout(&data).map(|()| 0)
+            }
+            SYS_WRITE => {
+                let [handle, data, len] = read_block(core, parameter)?;
+                let mut buf = vec![0; len.min(MAX_TRANSFER) as usize];
+                core.read_8(data.into(), &mut buf)?;
+                self.write(handle, &buf).map(|n| (len as usize - n) as i32)
+            }
+            SYS_READ => {
+                let [handle, data, len] = read_block(core, parameter)?;
+                let mut buf = vec![0; len.min(MAX_TRANSFER) as usize];
+                match self.read(handle, &mut buf) {
+                    Ok(n) => {
+                        core.write_8(data.into(), &buf[..n])?;
+                        Ok((len as usize - n) as i32)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            SYS_ISTTY => {
+                let [handle] = read_block(core, parameter)?;
+                self.handle(handle)
+                    .map(|handle| i32::from(!matches!(handle, Handle::File(_))))
+            }
+            SYS_SEEK => {
+                let [handle, position] = read_block(core, parameter)?;
+                match self.handle(handle) {
+                    Ok(Handle::File(file)) => {
+                        file.seek(SeekFrom::Start(position.into())).map(|_| 0)
+                    }
+                    Ok(_) => Err(io::ErrorKind::Unsupported.into()),
+                    Err(e) => Err(e),
+                }
+            }
+            SYS_FLEN => {
+                let [handle] = read_block(core, parameter)?;
+                match self.handle(handle) {
+                    Ok(Handle::File(file)) => file.metadata().map(|m| m.len() as i32),
+                    Ok(_) => Ok(0),
+                    Err(e) => Err(e),
+                }
+            }
+            SYS_CLOCK => Ok((self.start.elapsed().as_millis() / 10) as i32),
+            SYS_ERRNO => Ok(self.errno),
+            // e.g. `SYS_HEAPINFO` or `SYS_GET_CMDLINE`, which newlib calls before `main`
+            _ => {
+                log::debug!("unsupported semihosting operation 0x{:02x}", operation);
+                self.errno = ENOSYS;
+                return Ok(-1);
+            }
+        };
+
+        match result {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                log::debug!("semihosting operation 0x{:02x} failed: {}", operation, e);
+                self.errno = errno(&e);
+                Ok(-1)
+            }
+        }
+    }
+
+    /// Open `name` with an `fopen` mode, from 0 for `r` to 11 for `a+b`.
+    fn open(&mut self, name: &str, mode: u32) -> io::Result<i32> {
+        // the binary flag is bit 0, and `+` is bit 1
+        let update = mode & 2 != 0;
+        let handle = match (name, mode >> 2) {
+            (CONSOLE, 0) => Handle::Stdin,
+            (CONSOLE, 1) => Handle::Stdout,
+            (CONSOLE, 2) => Handle::Stderr,
+            (_, 0..=2) => {
+                let path = self.sandboxed(name)?;
+                log::debug!("opening `{}` with mode {}", path.display(), mode);
+                let mut options = OpenOptions::new();
+                match mode >> 2 {
+                    0 => options.read(true).write(update),
+                    1 => options.write(true).create(true).truncate(true).read(update),
+                    _ => options.append(true).create(true).read(update),
+                };
+                Handle::File(options.open(path)?)
+            }
+            _ => return Err(io::ErrorKind::InvalidInput.into()),
+        };
+
+        let index = match self.handles.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.handles.push(None);
+                self.handles.len() - 1
+            }
+        };
+        self.handles[index] = Some(handle);
+        Ok(index as i32 + 1)
+    }
+
+    fn close(&mut self, handle: u32) -> io::Result<i32> {
+        self.handle(handle)?;
+        self.handles[handle as usize - 1] = None;
+        Ok(0)
+    }
+
+    fn write(&mut self, handle: u32, data: &[u8]) -> io::Result<usize> {
+        match self.handle(handle)? {
+            Handle::Stdin => return Err(io::ErrorKind::Unsupported.into()),
+            Handle::Stdout => write_stdout(data)?,
+            Handle::Stderr => {
+                let mut stderr = io::stderr().lock();
+                stderr.write_all(data)?;
+                stderr.flush()?;
+            }
+            Handle::File(file) => file.write_all(data)?,
+        }
+        Ok(data.len())
+    }
+
+    fn read(&mut self, handle: u32, buf: &mut [u8]) -> io::Result<usize> {
+        match self.handle(handle)? {
+            Handle::Stdin => io::stdin().read(buf),
+            Handle::File(file) => file.read(buf),
+            _ => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    fn handle(&mut self, handle: u32) -> io::Result<&mut Handle> {
+        (handle as usize)
+            .checked_sub(1)
+            .and_then(|index| self.handles.get_mut(index))
+            .and_then(Option::as_mut)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid file handle"))
+    }
+
+    /// The path of `name` in the root directory.
+    ///
+    /// Absolute paths and `..` are rejected, so the program can't open files outside the root.
+    fn sandboxed(&self, name: &str) -> io::Result<PathBuf> {
+        let Some(root) = &self.root else {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "semihosting file I/O is disabled",
+            ));
+        };
+        let path = Path::new(name);
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("`{}` is outside the semihosting root", name),
+            ));
+        }
+        Ok(root.join(path))
+    }
+}
+
+/// The exit status of a semihosting `SYS_EXIT` or `SYS_EXIT_EXTENDED` request.
+///
+/// The operation is in `r0`, and the parameter in `r1`. `SYS_EXIT` passes the reason directly on
+/// 32-bit targets, and `SYS_EXIT_EXTENDED` passes a pointer to the reason and the status.
+pub(super) fn exit_status(core: &mut Core) -> Result<i32> {
+    let operation: u32 = core.read_core_reg(0)?;
+    let parameter: u32 = core.read_core_reg(1)?;
+
+    let (reason, status) = match operation {
+        SYS_EXIT => (parameter, 0),
+        SYS_EXIT_EXTENDED => {
+            let [reason, status] = read_block(core, parameter)?;
+            (reason, status as i32)
+        }
+        _ => bail!("unsupported semihosting operation 0x{:02x}", operation),
+    };
+
+    if reason == ADP_STOPPED_APPLICATION_EXIT {
+        Ok(status)
+    } else {
+        log::debug!("semihosting exit with reason 0x{:05x}", reason);
+        Ok(1)
+    }
+}
+
+/// Read the parameter block of a request.
+fn read_block<const N: usize>(core: &mut Core, address: u32) -> Result<[u32; N]> {
+    let mut block = [0; N];
+    core.read_32(address.into(), &mut block)?;
+    Ok(block)
+}
+
+/// Read a null-terminated string, without crossing a 32-byte boundary in a single read.
+fn read_c_string(core: &mut Core, mut address: u32) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    loop {
+        let mut chunk = [0; 32];
+        let chunk = &mut chunk[..32 - (address % 32) as usize];
+        core.read_8(address.into(), chunk)?;
+        match chunk.iter().position(|&b| b == 0) {
+            Some(end) => {
+                data.extend_from_slice(&chunk[..end]);
+                return Ok(data);
+            }
+            None => data.extend_from_slice(chunk),
+        }
+        address += chunk.len() as u32;
+    }
+}
+
+fn write_stdout(data: &[u8]) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(data)?;
+    stdout.flush()
+}
+
+/// The error number reported to the program by `SYS_ERRNO`.
+fn errno(error: &io::Error) -> i32 {
+    const ENOENT: i32 = 2;
+    const EIO: i32 = 5;
+    const EACCES: i32 = 13;
+    const EINVAL: i32 = 22;
+
+    error.raw_os_error().unwrap_or(match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sandbox_rejects_paths_outside_root() {
+        let semihosting = Semihosting::new(Some(PathBuf::from("root")));
+        for name in ["/etc/passwd", "..", "../x", "a/../../b"] {
+            let error = semihosting.sandboxed(name).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::PermissionDenied, "{}", name);
+        }
+        assert_eq!(
+            semihosting.sandboxed("./x").unwrap(),
+            Path::new("root").join("./x")
+        );
+    }
+
+    #[test]
+    fn sandbox_without_root_denies_files() {
+        let mut semihosting = Semihosting::new(None);
+        let error = semihosting.sandboxed("x").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        let error = semihosting.open("x", 0).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn console_modes_open_standard_streams() {
+        let mut semihosting = Semihosting::new(None);
+        assert_eq!(semihosting.open(CONSOLE, 0).unwrap(), 1);
+        assert_eq!(semihosting.open(CONSOLE, 4).unwrap(), 2);
+        assert_eq!(semihosting.open(CONSOLE, 8).unwrap(), 3);
+        assert!(matches!(semihosting.handle(1).unwrap(), Handle::Stdin));
+        assert!(matches!(semihosting.handle(2).unwrap(), Handle::Stdout));
+        assert!(matches!(semihosting.handle(3).unwrap(), Handle::Stderr));
+    }
+}
@@ -0,0 +1,204 @@
+use super::{Decoding, Destination, Routing};
+#[cfg(feature = "defmt")]
+use defmt_decoder::{Frame, Location};
+use eyre::{Context as _, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Output read from an RTT up channel of the program on `core`.
+pub enum Output<'a> {
+    /// Bytes from a channel that isn't decoded as `defmt`.
+    Bytes {
+        core: usize,
+        channel: usize,
+        name: Option<&'a str>,
+        data: &'a [u8],
+    },
+    /// A decoded `defmt` frame, and its location if known.
+    #[cfg(feature = "defmt")]
+    Frame {
+        core: usize,
+        channel: usize,
+        name: Option<&'a str>,
+        frame: &'a Frame<'a>,
+        location: Option<&'a Location>,
+    },
+}
+
+/// Receives the output of a [`Runner`](super::Runner).
+///
+/// This is implemented for closures, e.g. to collect frames in a test harness.
+pub trait Sink {
+    fn output(&mut self, output: Output<'_>) -> Result<()>;
+}
+
+impl<F> Sink for F
+where
+    F: FnMut(Output<'_>) -> Result<()>,
+{
+    fn output(&mut self, output: Output<'_>) -> Result<()> {
+        self(output)
+    }
+}
+
+/// Writes the output of each channel to the destination of its route.
+///
+/// Text and raw data written to stdout is printed, and `defmt` frames are logged with the `log`
+/// crate. `defmt` frames written to a file are formatted as text lines.
+pub struct RouteSink {
+    routing: Routing,
+    files: HashMap<PathBuf, File>,
+    /// The log target of `defmt` frames.
+    #[cfg(feature = "defmt")]
+    target: String,
+    /// Incomplete UTF-8 sequences from the last read, per core and channel.
+    pending: HashMap<(usize, usize), Vec<u8>>,
+    /// Prefix output with the core it was read from.
+    core_prefix: bool,
+    /// The cores and channels whose last text didn't end with a newline.
+    mid_line: HashSet<(usize, usize)>,
+}
+
+impl RouteSink {
+    /// Create or truncate all destination files.
+    ///
+    /// Opening a named pipe blocks until it's opened for reading.
+    pub fn new(routing: Routing) -> Result<Self> {
+        let mut files = HashMap::new();
+        for route in routing.routes.iter() {
+            if let Destination::File(path) = &route.destination {
+                if files.contains_key(path) {
+                    continue;
+                }
+                log::debug!("writing channel {} to `{}`", route.channel, path.display());
+                let file = File::create(path)
+                    .wrap_err_with(|| format!("failed to create `{}`", path.display()))?;
+                files.insert(path.clone(), file);
+            }
+        }
+        Ok(Self {
+            routing,
+            files,
+            #[cfg(feature = "defmt")]
+            target: "target".to_owned(),
+            pending: HashMap::new(),
+            core_prefix: false,
+            mid_line: HashSet::new(),
+        })
+    }
+
+    /// Prefix each line of text with the core it was read from, e.g. `[core 1] `, and log `defmt`
+    /// frames with the log target `target::core1`.
+    ///
+    /// Raw data is written unchanged.
+    pub fn with_core_prefix(mut self) -> Self {
+        self.core_prefix = true;
+        self
+    }
+
+    /// Use a different log target than `target` for `defmt` frames.
+    #[cfg(feature = "defmt")]
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    /// Prefix the lines of `text` with the core, continuing a line left open by the last read.
+    fn prefix_lines(&mut self, core: usize, channel: usize, text: &str) -> String {
+        let mut prefixed = String::new();
+        let mut mid_line = self.mid_line.contains(&(core, channel));
+        for line in text.split_inclusive('\n') {
+            if !mid_line {
+                prefixed.push_str(&format!("[core {}] ", core));
+            }
+            prefixed.push_str(line);
+            mid_line = !line.ends_with('\n');
+        }
+        if mid_line {
+            self.mid_line.insert((core, channel));
+        } else {
+            self.mid_line.remove(&(core, channel));
+        }
+        prefixed
+    }
+
+    fn writer(&self, channel: usize, name: Option<&str>) -> Box<dyn Write + '_> {
+        match self.routing.destination(channel, name) {
+            Destination::Stdout => Box::new(io::stdout().lock()),
+            Destination::File(path) => Box::new(self.files.get(path).expect("file not opened")),
+        }
+    }
+}
+
+impl Sink for RouteSink {
+    fn output(&mut self, output: Output<'_>) -> Result<()> {
+        match output {
+            Output::Bytes {
+                core,
+                channel,
+                name,
+                data,
+            } => {
+                let text = match self.routing.decoding(channel, name) {
+                    Decoding::Raw => None,
+                    _ => {
+                        let pending = self.pending.entry((core, channel)).or_default();
+                        pending.extend_from_slice(data);
+                        let text = decode_utf8(pending);
+                        if self.core_prefix {
+                            Some(self.prefix_lines(core, channel, &text))
+                        } else {
+                            Some(text)
+                        }
+                    }
+                };
+                let mut writer = self.writer(channel, name);
+                match &text {
+                    Some(text) => writer.write_all(text.as_bytes())?,
+                    None => writer.write_all(data)?,
+                }
+                writer.flush()?;
+            }
+            #[cfg(feature = "defmt")]
+            Output::Frame {
+                core,
+                channel,
+                name,
+                frame,
+                location,
+            } => match self.routing.destination(channel, name) {
+                Destination::Stdout if self.core_prefix => {
+                    let target = format!("{}::core{}", self.target, core);
+                    crate::defmt::log_frame(frame, location, &target)
+                }
+                Destination::Stdout => crate::defmt::log_frame(frame, location, &self.target),
+                Destination::File(_) => {
+                    let prefix = if self.core_prefix {
+                        format!("[core {}] ", core)
+                    } else {
+                        String::new()
+                    };
+                    let mut writer = self.writer(channel, name);
+                    writeln!(writer, "{}{}", prefix, frame.display(false))?;
+                    writer.flush()?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Decode as much of `data` as possible, leaving an incomplete UTF-8 sequence at the end.
+fn decode_utf8(data: &mut Vec<u8>) -> String {
+    let complete = match std::str::from_utf8(data) {
+        Ok(_) => data.len(),
+        // the sequence may be completed by the next read
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => data.len(),
+    };
+    let text = String::from_utf8_lossy(&data[..complete]).into_owned();
+    data.drain(..complete);
+    text
+}
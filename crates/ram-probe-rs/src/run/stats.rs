@@ -0,0 +1,108 @@
+use probe_rs::rtt::UpChannel;
+use std::time::{Duration, Instant};
+
+/// How often throughput statistics are reported.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The bytes read from an up channel, and how often its buffer was full.
+#[derive(Default)]
+struct Counts {
+    bytes: u64,
+    full: u64,
+}
+
+struct ChannelStats {
+    /// e.g. `up channel 0`, or `core 1 up channel 0` with programs on several cores.
+    name: String,
+    buffer_size: usize,
+    total: Counts,
+    /// The counts since the last report.
+    interval: Counts,
+}
+
+/// Throughput statistics of the up channels.
+///
+/// A channel's buffer is full if a read returns as many bytes as it can hold. The target then
+/// blocks, or drops data, depending on the channel's mode.
+pub(super) struct Stats {
+    channels: Vec<ChannelStats>,
+    /// Whether to report the statistics, or only log buffer full events.
+    report: bool,
+    start: Instant,
+    last_report: Instant,
+}
+
+impl Stats {
+    /// The `core` is named in the reports if given.
+    pub fn new(channels: &[UpChannel], report: bool, core: Option<usize>) -> Self {
+        let now = Instant::now();
+        Self {
+            channels: channels
+                .iter()
+                .map(|channel| ChannelStats {
+                    name: match core {
+                        Some(core) => format!("core {} up channel {}", core, channel.number()),
+                        None => format!("up channel {}", channel.number()),
+                    },
+                    buffer_size: channel.buffer_size(),
+                    total: Counts::default(),
+                    interval: Counts::default(),
+                })
+                .collect(),
+            report,
+            start: now,
+            last_report: now,
+        }
+    }
+
+    /// Record a read of `n` bytes from the channel at `index`.
+    pub fn record(&mut self, index: usize, n: usize) {
+        let channel = &mut self.channels[index];
+        channel.total.bytes += n as u64;
+        channel.interval.bytes += n as u64;
+        // the ring buffer holds one byte less than its size
+        if n + 1 >= channel.buffer_size {
+            log::debug!("{} buffer full", channel.name);
+            channel.total.full += 1;
+            channel.interval.full += 1;
+        }
+    }
+
+    /// Report the throughput since the last report, if it's due.
+    pub fn report_if_due(&mut self) {
+        let elapsed = self.last_report.elapsed();
+        if !self.report || elapsed < REPORT_INTERVAL {
+            return;
+        }
+        for channel in self.channels.iter_mut() {
+            let interval = std::mem::take(&mut channel.interval);
+            if interval.bytes > 0 {
+                log::info!(
+                    "{}: {:.0} bytes/s, buffer full {} times",
+                    channel.name,
+                    interval.bytes as f64 / elapsed.as_secs_f64(),
+                    interval.full
+                );
+            }
+        }
+        self.last_report = Instant::now();
+    }
+
+    /// Report the totals of the run.
+    pub fn summary(&self) {
+        if !self.report {
+            return;
+        }
+        let elapsed = self.start.elapsed();
+        for channel in self.channels.iter() {
+            log::info!(
+                "{}: {} bytes in {:.1?} ({:.0} bytes/s), buffer full {} times",
+                channel.name,
+                channel.total.bytes,
+                elapsed,
+                channel.total.bytes as f64 / elapsed.as_secs_f64(),
+                channel.total.full
+            );
+        }
+    }
+}